@@ -0,0 +1,77 @@
+//! Times list-heavy programs: quoting large nested lists and printing them
+//! with `string`. The programs use only features old enough to compare
+//! against a build from before values became a tagged enum, such as
+//! `ce124d2`, the parent of the commit that made that change (`7581d3f`):
+//!
+//! ```text
+//! git worktree add /tmp/before ce124d2 && cargo build --release --manifest-path /tmp/before/Cargo.toml
+//! LISP_BASELINE=/tmp/before/target/release/lisp cargo bench --bench lists
+//! ```
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+const LISP: &str = env!("CARGO_BIN_EXE_lisp");
+
+/// Prints a quoted list of `n` elements, each a small list holding a
+/// number, a string and a dotted pair, 50 times over.
+fn print_lists(n: usize) -> String {
+    let items: Vec<String> = (0..n)
+        .map(|i| format!("({i} \"s{i}\" (k . {i}))"))
+        .collect();
+    let form = format!("(string '({}))\n", items.join(" "));
+    form.repeat(50)
+}
+
+/// Runs `lisp` on `path`, returning the best time of a few runs.
+fn time_run(lisp: &str, path: &Path) -> Duration {
+    (0..3)
+        .map(|_| {
+            let start = Instant::now();
+            let output = Command::new(lisp).arg(path).output().unwrap();
+            let elapsed = start.elapsed();
+            // The programs print nothing unless they fail
+            assert!(
+                output.status.success() && output.stdout.is_empty(),
+                "{}",
+                String::from_utf8_lossy(&output.stdout)
+            );
+            elapsed
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let baseline = env::var("LISP_BASELINE").ok();
+    let dir = env::temp_dir().join(format!("lisp-bench-lists-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    print!("{:>8} {:>12}", "length", "time");
+    if baseline.is_some() {
+        print!(" {:>12} {:>8}", "baseline", "speedup");
+    }
+    println!();
+
+    for n in [250, 500, 1000, 2000] {
+        let path = dir.join(format!("{n}.lisp"));
+        fs::write(&path, print_lists(n)).unwrap();
+
+        let time = time_run(LISP, &path);
+        print!("{:>8} {:>12.2?}", n, time);
+        if let Some(baseline) = &baseline {
+            let before = time_run(baseline, &path);
+            print!(
+                " {:>12.2?} {:>7.1}x",
+                before,
+                before.as_secs_f64() / time.as_secs_f64()
+            );
+        }
+        println!();
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,81 @@
+//! Times the interpreter on generated programs of doubling size. Reading is
+//! linear when the time per byte stays roughly flat as the input grows.
+//!
+//! Run with `cargo bench --bench scaling`.
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+const LISP: &str = env!("CARGO_BIN_EXE_lisp");
+
+/// A single quoted list of `n` mixed tokens, so that lexing dominates the run
+fn one_large_form(n: usize) -> String {
+    let mut source = String::from("'(");
+    for i in 0..n {
+        source.push_str(&format!(
+            "sym-{i} {i} \"str {i}\" :key{i} {i}.5 |odd sym|\n"
+        ));
+    }
+    source.push(')');
+    source
+}
+
+/// `n` small top-level forms, each parsed and evaluated in turn
+fn many_forms(n: usize) -> String {
+    let mut source = String::new();
+    for i in 0..n {
+        source.push_str(&format!("(define x{i} '(a {i} \"b\"))\n"));
+    }
+    source
+}
+
+/// Runs the interpreter on `path`, returning the best time of a few runs.
+fn time_run(path: &Path) -> Duration {
+    (0..3)
+        .map(|_| {
+            let start = Instant::now();
+            let output = Command::new(LISP).arg(path).output().unwrap();
+            let elapsed = start.elapsed();
+            // The programs print nothing unless they fail
+            assert!(
+                output.status.success() && output.stdout.is_empty(),
+                "{}",
+                String::from_utf8_lossy(&output.stdout)
+            );
+            elapsed
+        })
+        .min()
+        .unwrap()
+}
+
+fn report(name: &str, dir: &Path, generate: fn(usize) -> String) {
+    println!("{name}");
+    println!("{:>12} {:>12} {:>10}", "bytes", "time", "ns/byte");
+
+    for shift in 0..5 {
+        let source = generate(10_000 << shift);
+        let path = dir.join(format!("{shift}.lisp"));
+        fs::write(&path, &source).unwrap();
+
+        let time = time_run(&path);
+        println!(
+            "{:>12} {:>12.2?} {:>10.1}",
+            source.len(),
+            time,
+            time.as_nanos() as f64 / source.len() as f64
+        );
+    }
+    println!();
+}
+
+fn main() {
+    let dir = std::env::temp_dir().join(format!("lisp-bench-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    report("one large form", &dir, one_large_form);
+    report("many top-level forms", &dir, many_forms);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
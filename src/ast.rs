@@ -1,13 +1,17 @@
 use std::fmt::Debug;
 
-use crate::token::{Location, Token, TokenValue};
+use crate::lisp::number::LispNumber;
+use crate::token::{LexerError, Location, Token, TokenValue};
 
 #[derive(Clone, Debug)]
 pub enum ASTNodeValue {
     List(Vec<ASTNode>),
+    /// `(a b . c)`, a list whose final tail is `c` rather than nil
+    DottedList(Vec<ASTNode>, Box<ASTNode>),
     Ident(String),
-    Number(f32),
+    Number(LispNumber),
     String(String),
+    Keyword(String),
     Quote(Box<ASTNode>),
 }
 
@@ -27,124 +31,185 @@ impl Debug for ASTNode {
     }
 }
 
-pub struct ASTParser {
-    pub input: Vec<Token>,
-    pub roots: Vec<ASTNode>,
+/// How deeply lists and quotes may nest in a form. Parsing, and everything
+/// that later walks the tree, recurses once per level, taking up to about
+/// 3 KiB of native stack in unoptimized builds.
+pub const MAX_NESTING: usize = 256;
+
+/// Parses a stream of tokens into top-level forms, one form per call to
+/// `next`, so a caller can evaluate each form as soon as it has been read.
+pub struct ASTParser<I: Iterator<Item = Result<Token, (Location, LexerError)>>> {
+    input: I,
+    depth: usize,
 }
 
 #[derive(Debug)]
 pub enum ASTParserError {
     TryFailed,
     MismatchedParenthesis,
+    MisplacedDot,
+    /// Nesting deeper than `MAX_NESTING`
+    TooDeep,
+    Lexer(LexerError),
 }
 
-impl ASTParser {
-    pub fn new(input: Vec<Token>) -> ASTParser {
-        ASTParser {
-            input,
-            roots: Vec::new(),
-        }
+impl<I: Iterator<Item = Result<Token, (Location, LexerError)>>> ASTParser<I> {
+    pub fn new(input: I) -> ASTParser<I> {
+        ASTParser { input, depth: 0 }
     }
 
-    pub fn peek(&self) -> Option<Token> {
-        self.input.iter().next().map(|t| t.clone())
-    }
-
-    pub fn next(&mut self) -> Option<Token> {
-        if self.input.is_empty() {
-            None
-        } else {
-            Some(self.input.remove(0))
+    fn next_token(&mut self) -> Result<Option<Token>, (Location, ASTParserError)> {
+        match self.input.next() {
+            None => Ok(None),
+            Some(Ok(t)) => Ok(Some(t)),
+            Some(Err((location, e))) => Err((location, ASTParserError::Lexer(e))),
         }
     }
 
-    pub fn push_node(&mut self, node: ASTNodeValue, location: Location) {
-        self.roots.push(ASTNode {
-            location,
-            value: node,
-        });
+    fn parse_node(&mut self, t: Token) -> Result<ASTNode, (Location, ASTParserError)> {
+        if !matches!(t.value, TokenValue::Quote | TokenValue::LParen) {
+            return self.parse_value(t);
+        }
+        if self.depth == MAX_NESTING {
+            return Err((t.location, ASTParserError::TooDeep));
+        }
+        self.depth += 1;
+        let node = self.parse_value(t);
+        self.depth -= 1;
+        node
     }
 
-    pub fn try_parse_one(&mut self) -> Result<(), ASTParserError> {
-        if let Some(t) = self.peek() {
-            match t.value {
-                TokenValue::Quote => {
-                    self.next();
-                    let mut p = ASTParser::new(std::mem::take(&mut self.input));
-                    match p.try_parse_one() {
-                        Err(e) => return Err(e),
-                        Ok(()) => {
-                            self.push_node(
-                                ASTNodeValue::Quote(Box::new(p.roots.remove(0))),
-                                t.location,
-                            );
-                            self.input = p.input;
-                        }
-                    }
-                }
-                TokenValue::Number(n) => {
-                    self.next();
-                    self.push_node(ASTNodeValue::Number(n), t.location);
-                }
-                TokenValue::Symbol(s) => {
-                    self.next();
-                    self.push_node(ASTNodeValue::Ident(s), t.location);
-                }
-                TokenValue::String(s) => {
-                    self.next();
-                    self.push_node(ASTNodeValue::String(s), t.location);
-                }
-                TokenValue::LParen => {
-                    self.next();
-
-                    let mut args = Vec::new();
-                    loop {
-                        if let Some(t) = self.peek() {
-                            match t.value {
-                                TokenValue::RParen => {
-                                    self.next();
-                                    break;
-                                }
-                                _ => {
-                                    let mut local_parser =
-                                        ASTParser::new(std::mem::take(&mut self.input));
-                                    if let Err(e) = local_parser.try_parse_one() {
-                                        return Err(e);
-                                    }
-                                    self.input = local_parser.input;
-                                    args.push(local_parser.roots.remove(0));
-                                }
+    fn parse_value(&mut self, t: Token) -> Result<ASTNode, (Location, ASTParserError)> {
+        let value = match t.value {
+            TokenValue::Quote => match self.next_token()? {
+                Some(quoted) => ASTNodeValue::Quote(Box::new(self.parse_node(quoted)?)),
+                None => return Err((t.location, ASTParserError::TryFailed)),
+            },
+            TokenValue::Number(n) => ASTNodeValue::Number(n),
+            TokenValue::Symbol(s) => ASTNodeValue::Ident(s),
+            TokenValue::String(s) => ASTNodeValue::String(s),
+            TokenValue::Keyword(k) => ASTNodeValue::Keyword(k),
+            TokenValue::LParen => {
+                let mut args = Vec::new();
+                loop {
+                    match self.next_token()? {
+                        Some(Token {
+                            value: TokenValue::RParen,
+                            ..
+                        }) => break,
+                        Some(Token {
+                            value: TokenValue::Dot,
+                            location,
+                        }) => {
+                            if args.is_empty() {
+                                return Err((location, ASTParserError::MisplacedDot));
                             }
-                        } else {
-                            return Err(ASTParserError::MismatchedParenthesis);
+                            return self.parse_dotted_tail(args, t.location);
+                        }
+                        Some(t) => args.push(self.parse_node(t)?),
+                        None => {
+                            return Err((t.location, ASTParserError::MismatchedParenthesis));
                         }
                     }
-
-                    self.push_node(ASTNodeValue::List(args), t.location);
                 }
-                _ => return Err(ASTParserError::TryFailed),
+                ASTNodeValue::List(args)
             }
+            TokenValue::RParen => return Err((t.location, ASTParserError::TryFailed)),
+            TokenValue::Dot => return Err((t.location, ASTParserError::MisplacedDot)),
+        };
+
+        Ok(ASTNode {
+            location: t.location,
+            value,
+        })
+    }
+
+    /// Parses the `c)` of `(a b . c)`, after the dot has been consumed.
+    fn parse_dotted_tail(
+        &mut self,
+        args: Vec<ASTNode>,
+        location: Location,
+    ) -> Result<ASTNode, (Location, ASTParserError)> {
+        let tail = match self.next_token()? {
+            Some(Token {
+                value: TokenValue::RParen | TokenValue::Dot,
+                location,
+            }) => return Err((location, ASTParserError::MisplacedDot)),
+            Some(t) => self.parse_node(t)?,
+            None => return Err((location, ASTParserError::MismatchedParenthesis)),
+        };
+
+        match self.next_token()? {
+            Some(Token {
+                value: TokenValue::RParen,
+                ..
+            }) => Ok(ASTNode {
+                location,
+                value: ASTNodeValue::DottedList(args, Box::new(tail)),
+            }),
+            Some(t) => Err((t.location, ASTParserError::MisplacedDot)),
+            None => Err((location, ASTParserError::MismatchedParenthesis)),
         }
+    }
+}
+
+impl<I: Iterator<Item = Result<Token, (Location, LexerError)>>> Iterator for ASTParser<I> {
+    type Item = Result<ASTNode, (Location, ASTParserError)>;
 
-        Ok(())
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_token() {
+            Ok(Some(t)) => Some(self.parse_node(t)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
     }
+}
 
-    pub fn try_parse_all(&mut self) -> Result<(), (Location, ASTParserError)> {
-        loop {
-            match self.peek() {
-                None => return Ok(()),
-                Some(tok) => match self.try_parse_one() {
-                    Ok(()) => {}
-                    Err(ASTParserError::TryFailed) => {
-                        if self.peek().is_none() {
-                            return Ok(());
-                        } else {
-                            return Err((tok.location, ASTParserError::TryFailed));
-                        }
-                    }
-                    Err(e) => return Err((tok.location, e)),
-                },
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Evaluator;
+    use crate::lisp::{function::expr_to_readable, internal::LispExpr};
+    use crate::token::Tokenizer;
+
+    fn parse(source: &str) -> Result<ASTNode, (Location, ASTParserError)> {
+        ASTParser::new(Tokenizer::new(source)).next().unwrap()
+    }
+
+    fn read(source: &str) -> LispExpr {
+        Evaluator::node_to_expr(parse(source).unwrap())
+    }
+
+    /// Checks that `source` prints as `printed`, which reads back as the
+    /// same value.
+    fn assert_reprints(source: &str, printed: &str) {
+        let value = read(source);
+        assert_eq!(expr_to_readable(&value).unwrap(), printed);
+        assert!(
+            read(printed).equal(&value),
+            "{} changed on re-reading",
+            source
+        );
+    }
+
+    #[test]
+    fn dotted_lists_round_trip() {
+        assert_reprints("(1 2 . 3)", "(1 2 . 3)");
+        assert_reprints("(a . b)", "(a . b)");
+        assert_reprints("((a . 1) (b . 2))", "((a . 1) (b . 2))");
+        assert_reprints("(a . (b c))", "(a b c)");
+        assert_reprints("(a . ())", "(a)");
+    }
+
+    #[test]
+    fn misplaced_dots_are_rejected() {
+        for source in ["(. a)", "(a . b c)", "(a . )", "(a . . b)", "."] {
+            assert!(
+                matches!(parse(source), Err((_, ASTParserError::MisplacedDot))),
+                "{} parsed as {:?}",
+                source,
+                parse(source)
+            );
         }
     }
 }
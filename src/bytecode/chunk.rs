@@ -0,0 +1,108 @@
+use std::fmt::Write;
+use std::rc::Rc;
+
+use crate::lisp::{function::expr_to_string, internal::LispExpr, symbol::Symbol};
+use crate::token::Location;
+
+/// One VM instruction. Operands index into the owning `Prototype`'s
+/// constants or nested prototypes, or address a local slot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    /// Push `constants[i]`
+    Const(u32),
+    /// Push slot `i` of the current frame
+    LoadLocal(u32),
+    /// Push a slot of the frame `depth` levels out from the current one
+    LoadOuter {
+        depth: u32,
+        slot: u32,
+    },
+    /// Pop into slot `i` of the current frame
+    StoreLocal(u32),
+    /// Push the global named by the symbol `constants[i]`
+    LoadGlobal(u32),
+    /// Pop and bind to the global named by the symbol `constants[i]`
+    DefineGlobal(u32),
+    Pop,
+    Dup,
+    Jump(u32),
+    /// Pop, and jump if the value is nil
+    JumpIfFalse(u32),
+    /// Push a closure of `protos[i]` over the current frame
+    Closure(u32),
+    /// Call the function below the top `argc` values with them as arguments
+    Call(u32),
+    /// Like `Call`, but replaces the current call frame
+    TailCall(u32),
+    Return,
+}
+
+/// A compiled function body, or a top-level form compiled as a function of
+/// no arguments.
+pub struct Prototype {
+    pub name: Symbol,
+    /// Number of required parameters
+    pub arity: u32,
+    /// Number of `&optional` parameters, which follow the required ones
+    pub optional: u32,
+    /// Whether a `&rest` parameter follows the positional ones
+    pub rest: bool,
+    /// Names of the `&key` parameters, which follow `&rest`
+    pub keys: Vec<Symbol>,
+    /// Names of the slots in each frame: parameters first, in the order they
+    /// are declared, then internal definitions
+    pub locals: Vec<Symbol>,
+    pub code: Vec<Op>,
+    /// Source location of the form each instruction was compiled from
+    pub locations: Vec<Location>,
+    pub constants: Vec<LispExpr>,
+    pub protos: Vec<Rc<Prototype>>,
+}
+
+/// Renders `proto` and every prototype nested in it as readable assembly.
+pub fn disassemble(proto: &Prototype) -> String {
+    let mut out = String::new();
+    disassemble_into(proto, &mut out);
+    out
+}
+
+fn disassemble_into(proto: &Prototype, out: &mut String) {
+    let _ = writeln!(
+        out,
+        "{} (arity {}{}{}{}, {} slots):",
+        proto.name.name(),
+        proto.arity,
+        if proto.optional > 0 {
+            " + &optional"
+        } else {
+            ""
+        },
+        if proto.rest { " + &rest" } else { "" },
+        if proto.keys.is_empty() { "" } else { " + &key" },
+        proto.locals.len()
+    );
+
+    for (pc, (op, location)) in proto.code.iter().zip(&proto.locations).enumerate() {
+        let _ = write!(
+            out,
+            "  {:04} {:>4}:{:<3} {:?}",
+            pc, location.row, location.col, op
+        );
+        match op {
+            Op::Const(i) | Op::LoadGlobal(i) | Op::DefineGlobal(i) => {
+                let constant = &proto.constants[*i as usize];
+                let _ = write!(out, "\t; {}", expr_to_string(constant).unwrap_or_default());
+            }
+            Op::Closure(i) => {
+                let _ = write!(out, "\t; {}", proto.protos[*i as usize].name.name());
+            }
+            _ => {}
+        }
+        out.push('\n');
+    }
+
+    for nested in &proto.protos {
+        out.push('\n');
+        disassemble_into(nested, out);
+    }
+}
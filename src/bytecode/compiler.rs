@@ -0,0 +1,415 @@
+use std::rc::Rc;
+
+use crate::ast::{ASTNode, ASTNodeValue};
+use crate::bytecode::chunk::{Op, Prototype};
+use crate::interpreter::{EvalError, Evaluator};
+use crate::lisp::symbol::{self, Symbol};
+use crate::lisp::{function::Params, internal::LispExpr};
+use crate::token::Location;
+
+/// A function being compiled.
+struct Scope {
+    name: Symbol,
+    arity: u32,
+    optional: u32,
+    rest: bool,
+    keys: Vec<Symbol>,
+    /// Names of the frame's slots, or `None` for a top-level form, whose
+    /// definitions are globals
+    locals: Option<Vec<Symbol>>,
+    code: Vec<Op>,
+    locations: Vec<Location>,
+    constants: Vec<LispExpr>,
+    protos: Vec<Rc<Prototype>>,
+}
+
+/// Compiles AST nodes to bytecode. Variables bound by an enclosing `lambda`
+/// are resolved to frame slots at compile time; anything else is looked up
+/// as a global when it runs.
+pub struct Compiler {
+    scopes: Vec<Scope>,
+    /// Location of the form being compiled, recorded for each instruction
+    location: Location,
+}
+
+enum Variable {
+    Local { depth: u32, slot: u32 },
+    Global,
+}
+
+fn ident(node: &ASTNode) -> Option<Symbol> {
+    match &node.value {
+        ASTNodeValue::Ident(s) => Some(Symbol::intern(s)),
+        _ => None,
+    }
+}
+
+/// Splits `(head args...)` when `head` is a symbol.
+fn form(node: &ASTNode) -> Option<(Symbol, &[ASTNode])> {
+    match &node.value {
+        ASTNodeValue::List(items) => {
+            let (head, args) = items.split_first()?;
+            Some((ident(head)?, args))
+        }
+        _ => None,
+    }
+}
+
+/// Collects the names defined by `define`s in a function body, without
+/// descending into quoted data or nested functions. A name that is already
+/// a local, such as a parameter, keeps its slot.
+fn collect_defines(nodes: &[ASTNode], names: &mut Vec<Symbol>) {
+    for node in nodes {
+        match form(node) {
+            Some((symbol::QUOTE | symbol::LAMBDA, _)) => {}
+            Some((symbol::DEFINE, args)) => {
+                let name = match args.first().map(|target| &target.value) {
+                    Some(ASTNodeValue::Ident(name)) => Some(Symbol::intern(name)),
+                    Some(ASTNodeValue::List(signature)) => signature.first().and_then(ident),
+                    _ => None,
+                };
+                if let Some(name) = name
+                    && !names.contains(&name)
+                {
+                    names.push(name);
+                }
+                // The value of `(define name value)` may define more names
+                if let [
+                    ASTNode {
+                        value: ASTNodeValue::Ident(_),
+                        ..
+                    },
+                    value @ ..,
+                ] = args
+                {
+                    collect_defines(value, names);
+                }
+            }
+            _ => {
+                if let ASTNodeValue::List(items) = &node.value {
+                    collect_defines(items, names);
+                }
+            }
+        }
+    }
+}
+
+impl Compiler {
+    /// Compiles a top-level form into a prototype of no arguments that
+    /// evaluates it.
+    pub fn compile(node: &ASTNode) -> Result<Rc<Prototype>, EvalError> {
+        let mut compiler = Compiler {
+            scopes: Vec::new(),
+            location: node.location,
+        };
+        compiler.scopes.push(Scope {
+            name: Symbol::intern("toplevel"),
+            arity: 0,
+            optional: 0,
+            rest: false,
+            keys: Vec::new(),
+            locals: None,
+            code: Vec::new(),
+            locations: Vec::new(),
+            constants: Vec::new(),
+            protos: Vec::new(),
+        });
+
+        compiler.compile_expr(node, true)?;
+        compiler.emit(Op::Return);
+        Ok(Rc::new(compiler.finish()))
+    }
+
+    fn scope(&mut self) -> &mut Scope {
+        self.scopes.last_mut().unwrap()
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        let location = self.location;
+        let scope = self.scope();
+        scope.locations.push(location);
+        scope.code.push(op);
+        scope.code.len() - 1
+    }
+
+    /// Points the jump at `at` to the next instruction to be emitted.
+    fn patch(&mut self, at: usize) {
+        let target = self.scope().code.len() as u32;
+        match &mut self.scope().code[at] {
+            Op::Jump(to) | Op::JumpIfFalse(to) => *to = target,
+            _ => unreachable!("patched a non-jump"),
+        }
+    }
+
+    fn constant(&mut self, value: LispExpr) -> u32 {
+        let constants = &mut self.scope().constants;
+        constants.push(value);
+        constants.len() as u32 - 1
+    }
+
+    fn emit_nil(&mut self) {
+        let i = self.constant(LispExpr::Null);
+        self.emit(Op::Const(i));
+    }
+
+    fn finish(&mut self) -> Prototype {
+        let scope = self.scopes.pop().unwrap();
+        Prototype {
+            name: scope.name,
+            arity: scope.arity,
+            optional: scope.optional,
+            rest: scope.rest,
+            keys: scope.keys,
+            locals: scope.locals.unwrap_or_default(),
+            code: scope.code,
+            locations: scope.locations,
+            constants: scope.constants,
+            protos: scope.protos,
+        }
+    }
+
+    fn resolve(&self, sym: Symbol) -> Variable {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some(locals) = &scope.locals
+                && let Some(slot) = locals.iter().rposition(|local| *local == sym)
+            {
+                return Variable::Local {
+                    depth: depth as u32,
+                    slot: slot as u32,
+                };
+            }
+        }
+        Variable::Global
+    }
+
+    /// Compiles `node` to leave its value on the stack. In tail position,
+    /// calls become tail calls.
+    fn compile_expr(&mut self, node: &ASTNode, tail: bool) -> Result<(), EvalError> {
+        let outer = std::mem::replace(&mut self.location, node.location);
+        let compiled = self.compile_node(node, tail);
+        self.location = outer;
+        compiled
+    }
+
+    fn compile_node(&mut self, node: &ASTNode, tail: bool) -> Result<(), EvalError> {
+        match &node.value {
+            ASTNodeValue::Ident(s) => {
+                let sym = Symbol::intern(s);
+                let op = match self.resolve(sym) {
+                    Variable::Local { depth: 0, slot } => Op::LoadLocal(slot),
+                    Variable::Local { depth, slot } => Op::LoadOuter { depth, slot },
+                    Variable::Global => Op::LoadGlobal(self.constant(LispExpr::Symbol(sym))),
+                };
+                self.emit(op);
+            }
+            ASTNodeValue::Quote(quoted) => {
+                let value = Evaluator::node_to_expr(*quoted.clone());
+                let i = self.constant(value);
+                self.emit(Op::Const(i));
+            }
+            ASTNodeValue::DottedList(..) => return Err(EvalError::ImproperList),
+            ASTNodeValue::List(items) if items.is_empty() => self.emit_nil(),
+            ASTNodeValue::List(items) => match form(node) {
+                Some((symbol::QUOTE, args)) => match args {
+                    [quoted] => {
+                        let i = self.constant(Evaluator::node_to_expr(quoted.clone()));
+                        self.emit(Op::Const(i));
+                    }
+                    _ => return Err(EvalError::BadSyntax("quote")),
+                },
+                Some((symbol::DEFINE, args)) => self.compile_define(args)?,
+                Some((symbol::LAMBDA, args)) => match args.split_first() {
+                    Some((params, body)) => self.compile_lambda(symbol::LAMBDA, params, body)?,
+                    None => return Err(EvalError::BadSyntax("lambda")),
+                },
+                Some((symbol::IF, args)) => self.compile_if(args, tail)?,
+                Some((symbol::COND, args)) => self.compile_cond(args, tail)?,
+                Some((symbol::BEGIN, args)) => self.compile_body(args, tail)?,
+                _ => {
+                    for item in items {
+                        self.compile_expr(item, false)?;
+                    }
+                    let argc = items.len() as u32 - 1;
+                    self.emit(if tail {
+                        Op::TailCall(argc)
+                    } else {
+                        Op::Call(argc)
+                    });
+                }
+            },
+            _ => {
+                let value = Evaluator::node_to_expr(node.clone());
+                let i = self.constant(value);
+                self.emit(Op::Const(i));
+            }
+        }
+        Ok(())
+    }
+
+    /// Compiles a sequence of forms, leaving only the last one's value.
+    fn compile_body(&mut self, body: &[ASTNode], tail: bool) -> Result<(), EvalError> {
+        let Some((last, init)) = body.split_last() else {
+            self.emit_nil();
+            return Ok(());
+        };
+
+        for node in init {
+            self.compile_expr(node, false)?;
+            self.emit(Op::Pop);
+        }
+        self.compile_expr(last, tail)
+    }
+
+    fn compile_define(&mut self, args: &[ASTNode]) -> Result<(), EvalError> {
+        let name = match args {
+            [name, value] if ident(name).is_some() => {
+                self.compile_expr(value, false)?;
+                ident(name).unwrap()
+            }
+            [signature, body @ ..] => {
+                let ASTNodeValue::List(signature) = &signature.value else {
+                    return Err(EvalError::BadSyntax("define"));
+                };
+                let Some((name, params)) = signature.split_first() else {
+                    return Err(EvalError::BadSyntax("define"));
+                };
+                let Some(name) = ident(name) else {
+                    return Err(EvalError::BadSyntax("define"));
+                };
+                let params = ASTNode {
+                    location: signature[0].location,
+                    value: ASTNodeValue::List(params.to_vec()),
+                };
+                self.compile_lambda(name, &params, body)?;
+                name
+            }
+            _ => return Err(EvalError::BadSyntax("define")),
+        };
+
+        let i = self.constant(LispExpr::Symbol(name));
+        match &self.scope().locals {
+            Some(locals) => {
+                let slot = locals.iter().rposition(|local| *local == name).unwrap() as u32;
+                self.emit(Op::StoreLocal(slot));
+            }
+            None => {
+                self.emit(Op::DefineGlobal(i));
+            }
+        }
+        self.emit(Op::Const(i));
+        Ok(())
+    }
+
+    fn compile_lambda(
+        &mut self,
+        name: Symbol,
+        params: &ASTNode,
+        body: &[ASTNode],
+    ) -> Result<(), EvalError> {
+        let ASTNodeValue::List(params) = &params.value else {
+            return Err(EvalError::BadSyntax("lambda"));
+        };
+        let Some(params) = params
+            .iter()
+            .map(ident)
+            .collect::<Option<Vec<_>>>()
+            .and_then(|params| Params::parse(&params))
+        else {
+            return Err(EvalError::BadSyntax("lambda"));
+        };
+
+        let mut locals: Vec<_> = params.names().collect();
+        collect_defines(body, &mut locals);
+
+        self.scopes.push(Scope {
+            name,
+            arity: params.required.len() as u32,
+            optional: params.optional.len() as u32,
+            rest: params.rest.is_some(),
+            keys: params.keys,
+            locals: Some(locals),
+            code: Vec::new(),
+            locations: Vec::new(),
+            constants: Vec::new(),
+            protos: Vec::new(),
+        });
+        let compiled = self.compile_body(body, true);
+        self.emit(Op::Return);
+        let proto = self.finish();
+        compiled?;
+
+        let protos = &mut self.scope().protos;
+        protos.push(Rc::new(proto));
+        let i = protos.len() as u32 - 1;
+        self.emit(Op::Closure(i));
+        Ok(())
+    }
+
+    /// `(if test then else?)`
+    fn compile_if(&mut self, args: &[ASTNode], tail: bool) -> Result<(), EvalError> {
+        let (test, then, otherwise) = match args {
+            [test, then] => (test, then, None),
+            [test, then, otherwise] => (test, then, Some(otherwise)),
+            _ => return Err(EvalError::BadSyntax("if")),
+        };
+
+        self.compile_expr(test, false)?;
+        let to_else = self.emit(Op::JumpIfFalse(0));
+        self.compile_expr(then, tail)?;
+        let to_end = self.emit(Op::Jump(0));
+        self.patch(to_else);
+        match otherwise {
+            Some(otherwise) => self.compile_expr(otherwise, tail)?,
+            None => self.emit_nil(),
+        }
+        self.patch(to_end);
+        Ok(())
+    }
+
+    /// `(cond (test body...)...)`, with the same rules as the evaluator's.
+    fn compile_cond(&mut self, clauses: &[ASTNode], tail: bool) -> Result<(), EvalError> {
+        let mut to_end = Vec::new();
+
+        for clause in clauses {
+            let ASTNodeValue::List(clause) = &clause.value else {
+                return Err(EvalError::BadSyntax("cond"));
+            };
+            let Some((test, body)) = clause.split_first() else {
+                return Err(EvalError::BadSyntax("cond"));
+            };
+
+            if ident(test) == Some(symbol::ELSE) {
+                // Like any clause without a body, `(else)` yields its test,
+                // which is true
+                if body.is_empty() {
+                    let i = self.constant(LispExpr::Symbol(symbol::T));
+                    self.emit(Op::Const(i));
+                } else {
+                    self.compile_body(body, tail)?;
+                }
+                to_end.push(self.emit(Op::Jump(0)));
+                break;
+            }
+
+            self.compile_expr(test, false)?;
+            if body.is_empty() {
+                self.emit(Op::Dup);
+                let next = self.emit(Op::JumpIfFalse(0));
+                to_end.push(self.emit(Op::Jump(0)));
+                self.patch(next);
+                self.emit(Op::Pop);
+            } else {
+                let next = self.emit(Op::JumpIfFalse(0));
+                self.compile_body(body, tail)?;
+                to_end.push(self.emit(Op::Jump(0)));
+                self.patch(next);
+            }
+        }
+
+        self.emit_nil();
+        for jump in to_end {
+            self.patch(jump);
+        }
+        Ok(())
+    }
+}
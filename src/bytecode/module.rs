@@ -0,0 +1,649 @@
+//! The `.lispc` file format for compiled programs.
+//!
+//! All integers are little endian. A file is laid out as:
+//!
+//! ```text
+//! header        b"LISPC\0", version: u16
+//! symbol table  count: u32, then each name as a string
+//! forms         count: u32, then each top-level form as a prototype
+//! ```
+//!
+//! A prototype is its name (a symbol index), `arity: u32`, `optional: u32`,
+//! `rest: u8`, its `&key` parameters and then the names of its frame slots
+//! (each as `count: u32` and symbol indices), its constant pool (`count:
+//! u32` and values), its code (`count: u32` and instructions), source
+//! locations for each instruction (`row, col, offset` as u32s) and finally
+//! its nested prototypes. Strings are a `u32` byte length followed by UTF-8. Symbols
+//! are written as indices into the symbol table so they can be re-interned
+//! on load.
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::ast::MAX_NESTING;
+use crate::bytecode::chunk::{Op, Prototype};
+use crate::lisp::{
+    bigint::BigInt, internal::LispExpr, number::LispNumber, ratio::Ratio, symbol::Symbol,
+};
+use crate::token::Location;
+
+const MAGIC: &[u8; 6] = b"LISPC\0";
+pub const VERSION: u16 = 8;
+
+#[derive(Debug)]
+pub enum ModuleError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+    InvalidUtf8,
+    BadTag(u8),
+    BadSymbol(u32),
+    BadNumber,
+    /// A prototype the VM can't run safely, such as one whose code indexes
+    /// past its constants or unbalances the stack
+    BadPrototype(&'static str),
+    /// Prototypes or lists nested more deeply than any source form could be
+    TooDeep,
+    /// A value that has no serialized form, such as a function
+    Unserializable,
+}
+
+mod tag {
+    pub const NULL: u8 = 0;
+    pub const INTEGER: u8 = 1;
+    pub const FLOAT: u8 = 2;
+    pub const STRING: u8 = 3;
+    pub const SYMBOL: u8 = 4;
+    pub const KEYWORD: u8 = 5;
+    /// A list as `count: u32`, its elements and then its final tail, so
+    /// long lists are read and written without recursing per element
+    pub const LIST: u8 = 6;
+    pub const BIG: u8 = 7;
+    pub const RATIO: u8 = 8;
+}
+
+mod opcode {
+    pub const CONST: u8 = 0;
+    pub const LOAD_LOCAL: u8 = 1;
+    pub const LOAD_OUTER: u8 = 2;
+    pub const STORE_LOCAL: u8 = 3;
+    pub const LOAD_GLOBAL: u8 = 4;
+    pub const DEFINE_GLOBAL: u8 = 5;
+    pub const POP: u8 = 6;
+    pub const DUP: u8 = 7;
+    pub const JUMP: u8 = 8;
+    pub const JUMP_IF_FALSE: u8 = 9;
+    pub const CLOSURE: u8 = 10;
+    pub const CALL: u8 = 11;
+    pub const TAIL_CALL: u8 = 12;
+    pub const RETURN: u8 = 13;
+}
+
+struct Writer {
+    out: Vec<u8>,
+    symbols: Vec<Symbol>,
+    symbol_ids: HashMap<Symbol, u32>,
+}
+
+impl Writer {
+    fn u8(&mut self, n: u8) {
+        self.out.push(n);
+    }
+
+    fn u32(&mut self, n: u32) {
+        self.out.extend_from_slice(&n.to_le_bytes());
+    }
+
+    fn u64(&mut self, n: u64) {
+        self.out.extend_from_slice(&n.to_le_bytes());
+    }
+
+    fn str(&mut self, s: &str) {
+        self.u32(s.len() as u32);
+        self.out.extend_from_slice(s.as_bytes());
+    }
+
+    /// A sign byte followed by the magnitude as hex text
+    fn big(&mut self, n: &BigInt) {
+        self.u8(n.is_negative() as u8);
+        self.str(&n.abs().to_str_radix(16));
+    }
+
+    fn symbol(&mut self, sym: Symbol) {
+        let next = self.symbols.len() as u32;
+        let id = *self.symbol_ids.entry(sym).or_insert(next);
+        if id == next {
+            self.symbols.push(sym);
+        }
+        self.u32(id);
+    }
+
+    fn value(&mut self, value: &LispExpr) -> Result<(), ModuleError> {
+        match value {
+            LispExpr::Null => self.u8(tag::NULL),
+            LispExpr::Number(LispNumber::Integer(n)) => {
+                self.u8(tag::INTEGER);
+                self.u64(*n as u64);
+            }
+            LispExpr::Number(LispNumber::Big(n)) => {
+                self.u8(tag::BIG);
+                self.big(n);
+            }
+            LispExpr::Number(LispNumber::Ratio(n)) => {
+                self.u8(tag::RATIO);
+                self.big(n.numer());
+                self.big(n.denom());
+            }
+            LispExpr::Number(LispNumber::Float(n)) => {
+                self.u8(tag::FLOAT);
+                self.u64(n.to_bits());
+            }
+            LispExpr::String(s) => {
+                self.u8(tag::STRING);
+                self.str(s);
+            }
+            LispExpr::Symbol(sym) => {
+                self.u8(tag::SYMBOL);
+                self.symbol(*sym);
+            }
+            LispExpr::Keyword(sym) => {
+                self.u8(tag::KEYWORD);
+                self.symbol(*sym);
+            }
+            LispExpr::Pair(_) => {
+                let mut iter = value.iter();
+                let items: Vec<_> = iter.by_ref().collect();
+                self.u8(tag::LIST);
+                self.u32(items.len() as u32);
+                for item in &items {
+                    self.value(item)?;
+                }
+                self.value(&iter.tail)?;
+            }
+            LispExpr::Function(_) => return Err(ModuleError::Unserializable),
+        }
+        Ok(())
+    }
+
+    fn op(&mut self, op: Op) {
+        let (code, operands): (u8, &[u32]) = match op {
+            Op::Const(i) => (opcode::CONST, &[i]),
+            Op::LoadLocal(i) => (opcode::LOAD_LOCAL, &[i]),
+            Op::LoadOuter { depth, slot } => (opcode::LOAD_OUTER, &[depth, slot]),
+            Op::StoreLocal(i) => (opcode::STORE_LOCAL, &[i]),
+            Op::LoadGlobal(i) => (opcode::LOAD_GLOBAL, &[i]),
+            Op::DefineGlobal(i) => (opcode::DEFINE_GLOBAL, &[i]),
+            Op::Pop => (opcode::POP, &[]),
+            Op::Dup => (opcode::DUP, &[]),
+            Op::Jump(i) => (opcode::JUMP, &[i]),
+            Op::JumpIfFalse(i) => (opcode::JUMP_IF_FALSE, &[i]),
+            Op::Closure(i) => (opcode::CLOSURE, &[i]),
+            Op::Call(i) => (opcode::CALL, &[i]),
+            Op::TailCall(i) => (opcode::TAIL_CALL, &[i]),
+            Op::Return => (opcode::RETURN, &[]),
+        };
+        self.u8(code);
+        for operand in operands {
+            self.u32(*operand);
+        }
+    }
+
+    fn prototype(&mut self, proto: &Prototype) -> Result<(), ModuleError> {
+        self.symbol(proto.name);
+        self.u32(proto.arity);
+        self.u32(proto.optional);
+        self.u8(proto.rest as u8);
+        self.u32(proto.keys.len() as u32);
+        for key in &proto.keys {
+            self.symbol(*key);
+        }
+        self.u32(proto.locals.len() as u32);
+        for local in &proto.locals {
+            self.symbol(*local);
+        }
+
+        self.u32(proto.constants.len() as u32);
+        for constant in &proto.constants {
+            self.value(constant)?;
+        }
+
+        self.u32(proto.code.len() as u32);
+        for op in &proto.code {
+            self.op(*op);
+        }
+        for location in &proto.locations {
+            self.u32(location.row as u32);
+            self.u32(location.col as u32);
+            self.u32(location.offset as u32);
+        }
+
+        self.u32(proto.protos.len() as u32);
+        for nested in &proto.protos {
+            self.prototype(nested)?;
+        }
+        Ok(())
+    }
+}
+
+/// Serializes compiled top-level forms, in order, as a `.lispc` file.
+pub fn write_module(forms: &[Rc<Prototype>]) -> Result<Vec<u8>, ModuleError> {
+    let mut body = Writer {
+        out: Vec::new(),
+        symbols: Vec::new(),
+        symbol_ids: HashMap::new(),
+    };
+    body.u32(forms.len() as u32);
+    for form in forms {
+        body.prototype(form)?;
+    }
+
+    // The symbol table is only complete once everything else is written
+    let mut file = Writer {
+        out: MAGIC.to_vec(),
+        symbols: Vec::new(),
+        symbol_ids: HashMap::new(),
+    };
+    file.out.extend_from_slice(&VERSION.to_le_bytes());
+    file.u32(body.symbols.len() as u32);
+    for sym in &body.symbols {
+        file.str(&sym.name());
+    }
+    file.out.extend_from_slice(&body.out);
+    Ok(file.out)
+}
+
+struct Reader<'a> {
+    input: &'a [u8],
+    symbols: Vec<Symbol>,
+    /// Lists being read inside one another
+    depth: usize,
+}
+
+impl Reader<'_> {
+    fn bytes(&mut self, n: usize) -> Result<&[u8], ModuleError> {
+        if self.input.len() < n {
+            return Err(ModuleError::Truncated);
+        }
+        let (bytes, rest) = self.input.split_at(n);
+        self.input = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, ModuleError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ModuleError> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, ModuleError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, ModuleError> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn str(&mut self) -> Result<&str, ModuleError> {
+        let len = self.u32()? as usize;
+        std::str::from_utf8(self.bytes(len)?).map_err(|_| ModuleError::InvalidUtf8)
+    }
+
+    fn big(&mut self) -> Result<BigInt, ModuleError> {
+        let negative = self.u8()? != 0;
+        let n = BigInt::parse_radix(self.str()?, 16).ok_or(ModuleError::BadNumber)?;
+        Ok(if negative { n.neg() } else { n })
+    }
+
+    fn symbol(&mut self) -> Result<Symbol, ModuleError> {
+        let id = self.u32()?;
+        self.symbols
+            .get(id as usize)
+            .copied()
+            .ok_or(ModuleError::BadSymbol(id))
+    }
+
+    fn value(&mut self) -> Result<LispExpr, ModuleError> {
+        Ok(match self.u8()? {
+            tag::NULL => LispExpr::Null,
+            tag::INTEGER => LispExpr::Number(LispNumber::Integer(self.u64()? as i64)),
+            tag::FLOAT => LispExpr::Number(LispNumber::Float(f64::from_bits(self.u64()?))),
+            tag::BIG => LispExpr::Number(LispNumber::from_big(self.big()?)),
+            tag::RATIO => {
+                let numer = self.big()?;
+                let denom = self.big()?;
+                if denom.is_zero() {
+                    return Err(ModuleError::BadNumber);
+                }
+                LispExpr::Number(LispNumber::from_ratio(Ratio::new(numer, denom)))
+            }
+            tag::STRING => LispExpr::String(self.str()?.into()),
+            tag::SYMBOL => LispExpr::Symbol(self.symbol()?),
+            tag::KEYWORD => LispExpr::Keyword(self.symbol()?),
+            tag::LIST => {
+                if self.depth == MAX_NESTING {
+                    return Err(ModuleError::TooDeep);
+                }
+                self.depth += 1;
+                let list = self.list();
+                self.depth -= 1;
+                list?
+            }
+            tag => return Err(ModuleError::BadTag(tag)),
+        })
+    }
+
+    fn list(&mut self) -> Result<LispExpr, ModuleError> {
+        let items = (0..self.u32()?)
+            .map(|_| self.value())
+            .collect::<Result<_, _>>()?;
+        Ok(LispExpr::list_with_tail(items, self.value()?))
+    }
+
+    fn op(&mut self) -> Result<Op, ModuleError> {
+        Ok(match self.u8()? {
+            opcode::CONST => Op::Const(self.u32()?),
+            opcode::LOAD_LOCAL => Op::LoadLocal(self.u32()?),
+            opcode::LOAD_OUTER => Op::LoadOuter {
+                depth: self.u32()?,
+                slot: self.u32()?,
+            },
+            opcode::STORE_LOCAL => Op::StoreLocal(self.u32()?),
+            opcode::LOAD_GLOBAL => Op::LoadGlobal(self.u32()?),
+            opcode::DEFINE_GLOBAL => Op::DefineGlobal(self.u32()?),
+            opcode::POP => Op::Pop,
+            opcode::DUP => Op::Dup,
+            opcode::JUMP => Op::Jump(self.u32()?),
+            opcode::JUMP_IF_FALSE => Op::JumpIfFalse(self.u32()?),
+            opcode::CLOSURE => Op::Closure(self.u32()?),
+            opcode::CALL => Op::Call(self.u32()?),
+            opcode::TAIL_CALL => Op::TailCall(self.u32()?),
+            opcode::RETURN => Op::Return,
+            code => return Err(ModuleError::BadTag(code)),
+        })
+    }
+
+    /// Reads and validates a prototype. `outer` holds the number of slots
+    /// of each enclosing prototype, innermost last.
+    fn prototype(&mut self, outer: &mut Vec<usize>) -> Result<Prototype, ModuleError> {
+        if outer.len() > MAX_NESTING {
+            return Err(ModuleError::TooDeep);
+        }
+
+        let name = self.symbol()?;
+        let arity = self.u32()?;
+        let optional = self.u32()?;
+        let rest = self.u8()? != 0;
+        let keys = (0..self.u32()?)
+            .map(|_| self.symbol())
+            .collect::<Result<_, _>>()?;
+        let locals: Vec<_> = (0..self.u32()?)
+            .map(|_| self.symbol())
+            .collect::<Result<_, _>>()?;
+
+        let constants = (0..self.u32()?)
+            .map(|_| self.value())
+            .collect::<Result<_, _>>()?;
+
+        let len = self.u32()?;
+        let code = (0..len).map(|_| self.op()).collect::<Result<_, _>>()?;
+        let locations = (0..len)
+            .map(|_| {
+                Ok(Location {
+                    row: self.u32()? as usize,
+                    col: self.u32()? as usize,
+                    offset: self.u32()? as usize,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+
+        outer.push(locals.len());
+        let protos = (0..self.u32()?)
+            .map(|_| self.prototype(outer).map(Rc::new))
+            .collect::<Result<_, _>>();
+        outer.pop();
+
+        let proto = Prototype {
+            name,
+            arity,
+            optional,
+            rest,
+            keys,
+            locals,
+            code,
+            locations,
+            constants,
+            protos: protos?,
+        };
+        validate(&proto, outer)?;
+        Ok(proto)
+    }
+}
+
+/// Checks that the VM can run `proto` without indexing out of bounds: every
+/// operand refers to something that exists, and every path through the code
+/// keeps the stack balanced and ends in a `Return`.
+fn validate(proto: &Prototype, outer: &[usize]) -> Result<(), ModuleError> {
+    let bad = |msg| Err(ModuleError::BadPrototype(msg));
+
+    let params =
+        proto.arity as usize + proto.optional as usize + proto.rest as usize + proto.keys.len();
+    if params > proto.locals.len() {
+        return bad("more parameters than slots");
+    }
+
+    let slot_in = |slots: usize, slot: u32| {
+        if (slot as usize) < slots {
+            Ok(())
+        } else {
+            bad("slot out of range")
+        }
+    };
+    let global = |i: u32| match proto.constants.get(i as usize) {
+        Some(LispExpr::Symbol(_)) => Ok(()),
+        _ => bad("global name is not a symbol"),
+    };
+
+    // Stack height on entry to each instruction reached so far
+    let mut heights = vec![None; proto.code.len()];
+    let mut pending = vec![(0, 0usize)];
+    while let Some((pc, height)) = pending.pop() {
+        let Some(&op) = proto.code.get(pc) else {
+            return bad("code runs past its end");
+        };
+        match heights[pc] {
+            Some(seen) if seen == height => continue,
+            Some(_) => return bad("unbalanced stack"),
+            None => heights[pc] = Some(height),
+        }
+
+        let (pops, pushes) = match op {
+            Op::Const(i) => {
+                if i as usize >= proto.constants.len() {
+                    return bad("constant out of range");
+                }
+                (0, 1)
+            }
+            Op::LoadLocal(slot) => {
+                slot_in(proto.locals.len(), slot)?;
+                (0, 1)
+            }
+            Op::LoadOuter { depth, slot } => {
+                let slots = match depth as usize {
+                    0 => Some(proto.locals.len()),
+                    depth => outer.len().checked_sub(depth).map(|i| outer[i]),
+                };
+                match slots {
+                    Some(slots) => slot_in(slots, slot)?,
+                    None => return bad("frame depth out of range"),
+                }
+                (0, 1)
+            }
+            Op::StoreLocal(slot) => {
+                slot_in(proto.locals.len(), slot)?;
+                (1, 0)
+            }
+            Op::LoadGlobal(i) => {
+                global(i)?;
+                (0, 1)
+            }
+            Op::DefineGlobal(i) => {
+                global(i)?;
+                (1, 0)
+            }
+            Op::Pop | Op::JumpIfFalse(_) | Op::Return => (1, 0),
+            Op::Dup => (1, 2),
+            Op::Jump(_) => (0, 0),
+            Op::Closure(i) => {
+                if i as usize >= proto.protos.len() {
+                    return bad("prototype out of range");
+                }
+                (0, 1)
+            }
+            Op::Call(argc) | Op::TailCall(argc) => (argc as usize + 1, 1),
+        };
+        let Some(height) = height.checked_sub(pops) else {
+            return bad("stack underflow");
+        };
+        let height = height + pushes;
+
+        match op {
+            Op::Return => {}
+            Op::Jump(to) => pending.push((to as usize, height)),
+            Op::JumpIfFalse(to) => {
+                pending.push((to as usize, height));
+                pending.push((pc + 1, height));
+            }
+            _ => pending.push((pc + 1, height)),
+        }
+    }
+
+    Ok(())
+}
+
+/// Loads the top-level forms of a `.lispc` file, ready to run on the VM.
+pub fn read_module(input: &[u8]) -> Result<Vec<Rc<Prototype>>, ModuleError> {
+    let mut reader = Reader {
+        input,
+        symbols: Vec::new(),
+        depth: 0,
+    };
+
+    if reader.bytes(MAGIC.len())? != MAGIC {
+        return Err(ModuleError::BadMagic);
+    }
+    let version = reader.u16()?;
+    if version != VERSION {
+        return Err(ModuleError::UnsupportedVersion(version));
+    }
+
+    for _ in 0..reader.u32()? {
+        let sym = Symbol::intern(reader.str()?);
+        reader.symbols.push(sym);
+    }
+
+    (0..reader.u32()?)
+        .map(|_| reader.prototype(&mut Vec::new()).map(Rc::new))
+        .collect()
+}
+
+/// Whether `input` starts like a `.lispc` file.
+pub fn is_module(input: &[u8]) -> bool {
+    input.starts_with(MAGIC)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lisp::symbol;
+
+    /// Round-trips a top-level prototype with `code` and `constants` through
+    /// the file format.
+    fn reload_with(
+        code: Vec<Op>,
+        constants: Vec<LispExpr>,
+    ) -> Result<Vec<Rc<Prototype>>, ModuleError> {
+        let location = Location {
+            row: 0,
+            col: 0,
+            offset: 0,
+        };
+        let proto = Prototype {
+            name: symbol::NIL,
+            arity: 0,
+            optional: 0,
+            rest: false,
+            keys: Vec::new(),
+            locals: Vec::new(),
+            locations: vec![location; code.len()],
+            code,
+            constants,
+            protos: Vec::new(),
+        };
+        read_module(&write_module(&[Rc::new(proto)])?)
+    }
+
+    /// Like `reload_with`, with a single nil constant.
+    fn reload(code: Vec<Op>) -> Result<Vec<Rc<Prototype>>, ModuleError> {
+        reload_with(code, vec![LispExpr::Null])
+    }
+
+    fn assert_rejected(code: Vec<Op>) {
+        let result = reload(code.clone());
+        assert!(
+            matches!(result, Err(ModuleError::BadPrototype(_))),
+            "{:?} loaded",
+            code
+        );
+    }
+
+    #[test]
+    fn well_formed_code_loads() {
+        assert!(reload(vec![Op::Const(0), Op::Return]).is_ok());
+        assert!(
+            reload(vec![
+                Op::Const(0),
+                Op::JumpIfFalse(4),
+                Op::Const(0),
+                Op::Jump(5),
+                Op::Const(0),
+                Op::Return,
+            ])
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn out_of_range_operands_are_rejected() {
+        assert_rejected(vec![Op::Const(1), Op::Return]);
+        assert_rejected(vec![Op::LoadLocal(0), Op::Return]);
+        assert_rejected(vec![Op::LoadOuter { depth: 1, slot: 0 }, Op::Return]);
+        assert_rejected(vec![Op::Closure(0), Op::Return]);
+        assert_rejected(vec![Op::Jump(7)]);
+        // The only constant is nil, not a symbol
+        assert_rejected(vec![Op::LoadGlobal(0), Op::Return]);
+    }
+
+    #[test]
+    fn unbalanced_code_is_rejected() {
+        assert_rejected(vec![Op::Const(0)]);
+        assert_rejected(vec![Op::Return]);
+        assert_rejected(vec![Op::Const(0), Op::Call(1), Op::Return]);
+        assert_rejected(vec![
+            Op::Const(0),
+            Op::JumpIfFalse(3),
+            Op::Const(0),
+            Op::Const(0),
+            Op::Return,
+        ]);
+    }
+
+    #[test]
+    fn long_lists_round_trip() {
+        let items = (0..300_000).map(|n| LispExpr::Number(LispNumber::Integer(n)));
+        let list = LispExpr::list_with_tail(items.collect(), LispExpr::String("end".into()));
+        let forms = reload_with(vec![Op::Const(0), Op::Return], vec![list.clone()]).unwrap();
+        assert!(forms[0].constants[0].equal(&list));
+    }
+}
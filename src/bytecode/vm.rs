@@ -0,0 +1,306 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::bytecode::chunk::{Op, Prototype};
+use crate::interpreter::{BACKTRACE_LIMIT, EvalError, Evaluator};
+use crate::lisp::{
+    function::{LispFunction, bind_args},
+    internal::LispExpr,
+};
+
+/// The local slots of one call of a compiled function. Frames live on the
+/// heap so closures can keep the frame they were created in.
+pub struct Frame {
+    proto: Rc<Prototype>,
+    /// `None` for an internal definition that hasn't been evaluated yet
+    slots: RefCell<Vec<Option<LispExpr>>>,
+    parent: Option<Rc<Frame>>,
+}
+
+impl Frame {
+    /// Creates the frame for calling `proto` with `args`, inside `parent`.
+    fn bind(
+        proto: &Rc<Prototype>,
+        parent: Option<Rc<Frame>>,
+        args: Vec<LispExpr>,
+    ) -> Result<Rc<Frame>, EvalError> {
+        let args = bind_args(
+            proto.arity as usize,
+            proto.optional as usize,
+            proto.rest,
+            &proto.keys,
+            args,
+        )?;
+        let mut slots: Vec<_> = args.into_iter().map(Some).collect();
+        slots.resize(proto.locals.len(), None);
+
+        Ok(Rc::new(Frame {
+            proto: proto.clone(),
+            slots: RefCell::new(slots),
+            parent,
+        }))
+    }
+
+    /// The value in `slot`, failing like the tree-walking evaluator if it
+    /// is read before its definition has run.
+    #[inline]
+    fn load(&self, slot: u32) -> Result<LispExpr, EvalError> {
+        match &self.slots.borrow()[slot as usize] {
+            Some(value) => Ok(value.clone()),
+            None => Err(self.unassigned(slot)),
+        }
+    }
+
+    #[cold]
+    fn unassigned(&self, slot: u32) -> EvalError {
+        let name = self.proto.locals[slot as usize];
+        EvalError::UnboundVariable(name.name().to_string())
+    }
+}
+
+struct CallFrame {
+    proto: Rc<Prototype>,
+    frame: Rc<Frame>,
+    pc: usize,
+    /// Stack height to restore on return
+    base: usize,
+}
+
+/// The state of a top-level form paused by running out of fuel or time,
+/// which `Vm::resume` carries on from.
+pub struct Paused {
+    stack: Vec<LispExpr>,
+    calls: Vec<CallFrame>,
+}
+
+/// A stack machine running compiled prototypes. Builtins, and closures made
+/// by the tree-walking evaluator, are called through the `Evaluator`, which
+/// also supplies the globals and enforces the depth and fuel limits.
+pub struct Vm<'a> {
+    eval: &'a Evaluator,
+    stack: Vec<LispExpr>,
+    calls: Vec<CallFrame>,
+    /// Whether running out of fuel or time between instructions leaves the
+    /// VM paused rather than abandoning its work
+    pausable: bool,
+    paused: bool,
+}
+
+impl<'a> Vm<'a> {
+    pub fn new(eval: &'a Evaluator) -> Vm<'a> {
+        Vm {
+            eval,
+            stack: Vec::new(),
+            calls: Vec::new(),
+            pausable: false,
+            paused: false,
+        }
+    }
+
+    /// Runs a top-level form compiled by `Compiler::compile`. If fuel or
+    /// time runs out partway, the error is returned and `into_paused` gives
+    /// the state to resume from, unless it ran out inside a builtin's call
+    /// back into Lisp, which can't be paused.
+    pub fn run(&mut self, proto: Rc<Prototype>) -> Result<LispExpr, EvalError> {
+        let frame = Frame::bind(&proto, None, Vec::new())?;
+        self.push_call(proto, frame, self.stack.len())?;
+        self.pausable = true;
+        self.execute()
+    }
+
+    /// Carries on with a form paused by `run`, from the instruction it
+    /// stopped before.
+    pub fn resume(&mut self, paused: Paused) -> Result<LispExpr, EvalError> {
+        self.stack = paused.stack;
+        self.calls = paused.calls;
+        self.pausable = true;
+        self.execute_until(0)
+    }
+
+    /// The state of a form that ran out of fuel or time, if it was paused.
+    pub fn into_paused(self) -> Option<Paused> {
+        self.paused.then_some(Paused {
+            stack: self.stack,
+            calls: self.calls,
+        })
+    }
+
+    /// Calls a compiled closure with already evaluated arguments.
+    pub fn call_closure(
+        &mut self,
+        proto: &Rc<Prototype>,
+        parent: &Rc<Frame>,
+        args: Vec<LispExpr>,
+    ) -> Result<LispExpr, EvalError> {
+        let frame = Frame::bind(proto, Some(parent.clone()), args)?;
+        self.push_call(proto.clone(), frame, self.stack.len())?;
+        self.execute()
+    }
+
+    fn push_call(
+        &mut self,
+        proto: Rc<Prototype>,
+        frame: Rc<Frame>,
+        base: usize,
+    ) -> Result<(), EvalError> {
+        if self.calls.len() >= self.eval.max_frames() {
+            return Err(self.with_backtrace(EvalError::StackOverflow {
+                backtrace: Vec::new(),
+            }));
+        }
+
+        self.calls.push(CallFrame {
+            proto,
+            frame,
+            pc: 0,
+            base,
+        });
+        Ok(())
+    }
+
+    /// Adds the VM's call frames, innermost first, to the backtrace of a
+    /// `StackOverflow`.
+    fn with_backtrace(&self, error: EvalError) -> EvalError {
+        match error {
+            EvalError::StackOverflow { mut backtrace } => {
+                let room = BACKTRACE_LIMIT.saturating_sub(backtrace.len());
+                backtrace.extend(
+                    self.calls
+                        .iter()
+                        .rev()
+                        .take(room)
+                        .map(|call| call.proto.name.name().to_string()),
+                );
+                EvalError::StackOverflow { backtrace }
+            }
+            error => error,
+        }
+    }
+
+    fn pop(&mut self) -> LispExpr {
+        self.stack.pop().expect("VM stack underflow")
+    }
+
+    /// Runs until the call frame on top when this was called returns.
+    fn execute(&mut self) -> Result<LispExpr, EvalError> {
+        self.execute_until(self.calls.len() - 1)
+    }
+
+    /// Runs until the call frame at index `entry` returns.
+    fn execute_until(&mut self, entry: usize) -> Result<LispExpr, EvalError> {
+        loop {
+            if let Err(e) = self.eval.step() {
+                // Between instructions the VM's state is complete, so it can
+                // carry on from here later
+                self.paused = self.pausable;
+                return Err(e);
+            }
+
+            let call = self.calls.last_mut().unwrap();
+            let op = call.proto.code[call.pc];
+            call.pc += 1;
+
+            match op {
+                Op::Const(i) => {
+                    let value = call.proto.constants[i as usize].clone();
+                    self.stack.push(value);
+                }
+                Op::LoadLocal(slot) => {
+                    let value = call.frame.load(slot)?;
+                    self.stack.push(value);
+                }
+                Op::LoadOuter { depth, slot } => {
+                    let mut frame = &call.frame;
+                    for _ in 0..depth {
+                        frame = frame.parent.as_ref().expect("frame depth out of range");
+                    }
+                    let value = frame.load(slot)?;
+                    self.stack.push(value);
+                }
+                Op::StoreLocal(slot) => {
+                    let frame = call.frame.clone();
+                    frame.slots.borrow_mut()[slot as usize] = Some(self.pop());
+                }
+                Op::LoadGlobal(i) => {
+                    let LispExpr::Symbol(sym) = call.proto.constants[i as usize] else {
+                        unreachable!("global name is not a symbol");
+                    };
+                    match self.eval.context().globals.get(sym) {
+                        Some(value) => self.stack.push(value),
+                        None => return Err(EvalError::UnboundVariable(sym.name().to_string())),
+                    }
+                }
+                Op::DefineGlobal(i) => {
+                    let LispExpr::Symbol(sym) = call.proto.constants[i as usize] else {
+                        unreachable!("global name is not a symbol");
+                    };
+                    let value = self.pop();
+                    self.eval.context().globals.define(sym, value);
+                }
+                Op::Pop => {
+                    self.pop();
+                }
+                Op::Dup => {
+                    let value = self.stack.last().expect("VM stack underflow").clone();
+                    self.stack.push(value);
+                }
+                Op::Jump(to) => call.pc = to as usize,
+                Op::JumpIfFalse(to) => {
+                    if !self.pop().is_truthy() {
+                        self.calls.last_mut().unwrap().pc = to as usize;
+                    }
+                }
+                Op::Closure(i) => {
+                    let closure = LispFunction::Compiled {
+                        proto: call.proto.protos[i as usize].clone(),
+                        frame: call.frame.clone(),
+                    };
+                    self.stack.push(LispExpr::Function(Rc::new(closure)));
+                }
+                Op::Call(argc) => self.call(argc as usize, false)?,
+                Op::TailCall(argc) => self.call(argc as usize, true)?,
+                Op::Return => {
+                    let value = self.pop();
+                    let call = self.calls.pop().unwrap();
+                    self.stack.truncate(call.base);
+                    if self.calls.len() == entry {
+                        return Ok(value);
+                    }
+                    self.stack.push(value);
+                }
+            }
+        }
+    }
+
+    fn call(&mut self, argc: usize, tail: bool) -> Result<(), EvalError> {
+        let args = self.stack.split_off(self.stack.len() - argc);
+        let callee = self.pop();
+
+        let LispExpr::Function(func) = &callee else {
+            return Err(EvalError::NotCallable(callee.ltype()));
+        };
+
+        match func.as_ref() {
+            LispFunction::Compiled { proto, frame } => {
+                let frame = Frame::bind(proto, Some(frame.clone()), args)?;
+                let base = if tail {
+                    // Reuse the caller's place on the stack
+                    let caller = self.calls.pop().unwrap();
+                    self.stack.truncate(caller.base);
+                    caller.base
+                } else {
+                    self.stack.len()
+                };
+                self.push_call(proto.clone(), frame, base)
+            }
+            _ => {
+                let value = self
+                    .eval
+                    .apply(&callee, args)
+                    .map_err(|e| self.with_backtrace(e))?;
+                self.stack.push(value);
+                Ok(())
+            }
+        }
+    }
+}
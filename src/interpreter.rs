@@ -1,143 +1,1028 @@
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::Instant;
+
 use crate::ast::{ASTNode, ASTNodeValue};
-use crate::lisp::function::LispFunction;
+use crate::bytecode::{
+    chunk::Prototype,
+    compiler::Compiler,
+    vm::{Paused, Vm},
+};
+use crate::lisp::env::Env;
+use crate::lisp::function::{LispFunction, Params, bind_args, expr_to_string};
 use crate::lisp::internal::*;
+use crate::lisp::random::Rng;
+use crate::lisp::symbol::{self, Symbol};
+
+/// Native stack size of the thread `main` runs the evaluator on.
+pub const STACK_SIZE: usize = 256 << 20;
+
+/// Native stack budgeted for each level of nesting. Unoptimized builds take
+/// up to about 5 KiB a level, most when a builtin like `map` calls back into
+/// the VM; optimized builds take under a third as much.
+const STACK_PER_LEVEL: usize = 8 << 10;
+
+/// Default for `Evaluator::set_max_depth`, sized to fit in `STACK_SIZE`.
+pub const DEFAULT_MAX_DEPTH: usize = STACK_SIZE / STACK_PER_LEVEL;
+
+/// Default for `Evaluator::set_max_frames`. VM call frames live on the
+/// heap, at around 200 bytes each, so they can nest far deeper than
+/// native recursion.
+pub const DEFAULT_MAX_FRAMES: usize = 1 << 20;
+
+/// How many steps pass between checks of the deadline, which needs a clock
+/// read
+const DEADLINE_INTERVAL: u64 = 256;
+
+/// Number of innermost frames kept in a `StackOverflow` backtrace
+pub(crate) const BACKTRACE_LIMIT: usize = 32;
+
+/// How `Evaluator::run` executes top-level forms.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    /// Walk each form's expression tree directly
+    TreeWalk,
+    /// Compile each form to bytecode and run it on the VM
+    Bytecode,
+}
+
 pub struct Evaluator {
-    input: Vec<ASTNode>,
     context: Context,
+    backend: Backend,
+    max_depth: usize,
+    depth: Cell<usize>,
+    max_frames: usize,
+    /// Steps left before `EvalError::OutOfFuel`, or `None` for no limit
+    fuel: Cell<Option<u64>>,
+    fuel_consumed: Cell<u64>,
+    deadline: Option<Instant>,
+    /// The top-level form paused by running out of fuel or time, to be
+    /// carried on with by the next `run` or `run_compiled`
+    paused: Option<Paused>,
 }
 
 #[derive(Debug)]
 pub enum EvalError {
-    AAAA,
+    UnboundVariable(String),
+    NotCallable(LispTypeId),
+    TypeMismatch {
+        expected: LispTypeId,
+        found: LispTypeId,
+    },
+    ArityMismatch {
+        expected: usize,
+        found: usize,
+    },
+    /// An improper list was evaluated as a call
+    ImproperList,
+    /// Evaluation nested deeper than the evaluator's max depth. The
+    /// backtrace lists the operators of the innermost calls, innermost
+    /// first.
+    StackOverflow {
+        backtrace: Vec<String>,
+    },
+    /// The step budget set with `Evaluator::set_fuel` ran out
+    OutOfFuel,
+    /// The deadline set with `Evaluator::set_deadline` passed
+    Timeout,
+    /// Fuel or time ran out where evaluation can't be paused, inside a
+    /// builtin calling back into Lisp, so the top-level form was abandoned
+    Unresumable(Box<EvalError>),
+    /// A special form was used with the wrong shape, e.g. `(lambda 1)`
+    BadSyntax(&'static str),
+    /// A radix outside 2 to 36, or other than 10 for a float
+    BadRadix,
+    /// An exact number divided by exact zero
+    DivisionByZero,
+    /// An argument outside the range its function accepts, e.g. a shift
+    /// count too large to represent the result
+    OutOfRange,
+    /// A keyword argument the function doesn't accept, a keyword with no
+    /// value after it, or a non-keyword where a keyword was expected
+    BadKeywordArgument(String),
+    /// A `format` control string that doesn't parse, or that needs more
+    /// arguments than it was given
+    BadFormat(&'static str),
+}
+
+/// The rest of an evaluation step: either a finished value, or a form in
+/// tail position that still has to be evaluated in the given scope.
+enum Tail {
+    Value(LispExpr),
+    Eval(LispExpr, Rc<Env>),
 }
 
 impl Evaluator {
-    pub fn new(input: Vec<ASTNode>) -> Evaluator {
+    pub fn new() -> Evaluator {
         Evaluator {
-            input,
             context: Context::default(),
+            backend: Backend::TreeWalk,
+            max_depth: DEFAULT_MAX_DEPTH,
+            depth: Cell::new(0),
+            max_frames: DEFAULT_MAX_FRAMES,
+            fuel: Cell::new(None),
+            fuel_consumed: Cell::new(0),
+            deadline: None,
+            paused: None,
         }
     }
 
-    pub fn peek(&self) -> Option<ASTNode> {
-        self.input.iter().next().map(|n| n.clone())
+    /// Limits how deeply evaluations may nest on the native stack before
+    /// failing with `EvalError::StackOverflow`. Every non-tail call in the
+    /// tree-walker nests a level, as does every call from a builtin or the
+    /// tree-walker into Lisp code; calls between compiled functions don't.
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
     }
 
-    pub fn next(&mut self) -> Option<ASTNode> {
-        if self.input.is_empty() {
-            None
-        } else {
-            Some(self.input.remove(0))
+    /// Sets the max depth to what fits in a native stack of `stack_size`
+    /// bytes, for running on a thread other than the one `main` spawns.
+    pub fn set_stack_size(&mut self, stack_size: usize) {
+        self.max_depth = stack_size / STACK_PER_LEVEL;
+    }
+
+    /// Limits how many call frames the VM may stack up for calls between
+    /// compiled functions, which use the heap rather than the native stack.
+    pub fn set_max_frames(&mut self, max_frames: usize) {
+        self.max_frames = max_frames;
+    }
+
+    pub(crate) fn max_frames(&self) -> usize {
+        self.max_frames
+    }
+
+    pub fn set_backend(&mut self, backend: Backend) {
+        self.backend = backend;
+    }
+
+    /// Reseeds the generator behind `random`, so runs are reproducible.
+    pub fn set_random_seed(&mut self, seed: u64) {
+        *self.context.rng.borrow_mut() = Rng::seeded(seed);
+    }
+
+    pub(crate) fn context(&self) -> &Context {
+        &self.context
+    }
+
+    /// Limits evaluation to `fuel` more steps, or removes the limit with
+    /// `None`. A step is one VM instruction, or one evaluation of a form by
+    /// the tree-walker. Fuel is only checked between steps, so a single
+    /// builtin call, like an `expt` with a huge result, runs to completion
+    /// however long it takes.
+    ///
+    /// While fuel or a deadline is set, forms run on the VM whatever the
+    /// backend, so they can be paused. When the fuel runs out, `run` returns
+    /// `EvalError::OutOfFuel` and keeps the paused form; after refuelling
+    /// with `add_fuel`, a further call to `run` carries on with it from where
+    /// it stopped and then with the remaining forms. If the fuel runs out
+    /// inside a builtin's call back into Lisp, such as the function passed to
+    /// `map`, the form can't be paused and is abandoned with
+    /// `EvalError::Unresumable` instead.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel.set(fuel);
+    }
+
+    /// Extends a budget set with `set_fuel`. Does nothing without a limit.
+    pub fn add_fuel(&mut self, fuel: u64) {
+        if let Some(left) = self.fuel.get() {
+            self.fuel.set(Some(left.saturating_add(fuel)));
         }
     }
 
-    pub fn try_interpret_next(&mut self) -> Result<LispExpr, EvalError> {
-        if let Some(node) = self.next() {
-            match node.value {
-                ASTNodeValue::String(s) => {
-                    return Ok(LispExpr::Literal(Box::new(LispString { value: s.clone() })));
-                }
-                ASTNodeValue::Number(n) => {
-                    return Ok(LispExpr::Literal(Box::new(LispNumber { value: n })));
+    pub fn fuel_remaining(&self) -> Option<u64> {
+        self.fuel.get()
+    }
+
+    /// Total steps evaluated by this evaluator, whether or not fuel is
+    /// limited.
+    pub fn fuel_consumed(&self) -> u64 {
+        self.fuel_consumed.get()
+    }
+
+    /// Fails evaluation with `EvalError::Timeout` once `deadline` has
+    /// passed, or removes the deadline with `None`. Like running out of fuel,
+    /// this pauses the current top-level form, for the next `run` to carry on
+    /// with once the deadline has been moved, and isn't noticed during a
+    /// single builtin call.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    /// Drops the form paused by running out of fuel or time, so the next
+    /// `run` doesn't carry on with it.
+    pub fn abandon_paused(&mut self) {
+        self.paused = None;
+    }
+
+    /// Whether fuel or a deadline may interrupt evaluation.
+    fn is_limited(&self) -> bool {
+        self.fuel.get().is_some() || self.deadline.is_some()
+    }
+
+    /// Accounts for one evaluation step against the fuel and deadline.
+    pub(crate) fn step(&self) -> Result<(), EvalError> {
+        let consumed = self.fuel_consumed.get() + 1;
+        self.fuel_consumed.set(consumed);
+
+        if let Some(left) = self.fuel.get() {
+            if left == 0 {
+                return Err(EvalError::OutOfFuel);
+            }
+            self.fuel.set(Some(left - 1));
+        }
+
+        if let Some(deadline) = self.deadline
+            && consumed.is_multiple_of(DEADLINE_INTERVAL)
+            && Instant::now() >= deadline
+        {
+            return Err(EvalError::Timeout);
+        }
+
+        Ok(())
+    }
+
+    pub fn node_to_expr(node: ASTNode) -> LispExpr {
+        match node.value {
+            ASTNodeValue::String(s) => LispExpr::String(s.into()),
+            ASTNodeValue::Number(n) => LispExpr::Number(n),
+            ASTNodeValue::List(l) => {
+                LispExpr::list(l.into_iter().map(Self::node_to_expr).collect())
+            }
+            ASTNodeValue::DottedList(l, tail) => LispExpr::list_with_tail(
+                l.into_iter().map(Self::node_to_expr).collect(),
+                Self::node_to_expr(*tail),
+            ),
+            ASTNodeValue::Ident(s) => LispExpr::Symbol(Symbol::intern(&s)),
+            ASTNodeValue::Keyword(k) => LispExpr::Keyword(Symbol::intern(&k)),
+            ASTNodeValue::Quote(quoted) => LispExpr::list(vec![
+                LispExpr::Symbol(symbol::QUOTE),
+                Self::node_to_expr(*quoted),
+            ]),
+        }
+    }
+
+    pub fn evaluate_expr(&self, expr: LispExpr, env: &Rc<Env>) -> Result<LispExpr, EvalError> {
+        let depth = self.depth.get();
+        if depth >= self.max_depth {
+            return Err(EvalError::StackOverflow {
+                backtrace: Vec::new(),
+            });
+        }
+
+        self.depth.set(depth + 1);
+        let result = self.evaluate_tail(expr.clone(), env);
+        self.depth.set(depth);
+
+        match result {
+            Err(EvalError::StackOverflow { mut backtrace }) => {
+                if let LispExpr::Pair(pair) = &expr
+                    && backtrace.len() < BACKTRACE_LIMIT
+                {
+                    backtrace.push(expr_to_string(&pair.car).unwrap_or_default());
                 }
-                ASTNodeValue::List(l) => {
-                    let mut local_eval = Evaluator::new(l);
-                    let mut args = Vec::new();
-                    while let Some(_) = local_eval.peek() {
-                        match local_eval.try_interpret_next() {
-                            Ok(expr) => {
-                                args.push(expr);
-                            }
-                            Err(e) => {
-                                return Err(e);
-                            }
-                        }
+                Err(EvalError::StackOverflow { backtrace })
+            }
+            result => result,
+        }
+    }
+
+    fn evaluate_tail(&self, expr: LispExpr, env: &Rc<Env>) -> Result<LispExpr, EvalError> {
+        // Forms in tail position come back as `Tail::Eval` and are evaluated
+        // by this loop instead of a nested call, so tail calls run in
+        // constant stack space.
+        let mut tail = Tail::Eval(expr, env.clone());
+        loop {
+            let (expr, env) = match tail {
+                Tail::Value(value) => return Ok(value),
+                Tail::Eval(expr, env) => (expr, env),
+            };
+            self.step()?;
+
+            tail = match &expr {
+                LispExpr::Pair(pair) => self.eval_form(pair, &env)?,
+                LispExpr::Symbol(sym) => match env.get(*sym) {
+                    Some(value) => Tail::Value(value),
+                    None => return Err(EvalError::UnboundVariable(sym.name().to_string())),
+                },
+                _ => Tail::Value(expr),
+            };
+        }
+    }
+
+    fn eval_form(&self, pair: &LispPair, env: &Rc<Env>) -> Result<Tail, EvalError> {
+        let rest = pair.cdr.list_to_vec()?;
+
+        if let LispExpr::Symbol(sym) = pair.car {
+            match sym {
+                symbol::QUOTE => return Ok(Tail::Value(Self::eval_quote(rest)?)),
+                symbol::DEFINE => return Ok(Tail::Value(self.eval_define(rest, env)?)),
+                symbol::LAMBDA => match rest.split_first() {
+                    Some((params, body)) => {
+                        return Ok(Tail::Value(Self::make_lambda(sym, params, body, env)?));
                     }
-                    return Ok(LispExpr::Literal(Box::new(LispList { value: args })));
-                }
-                ASTNodeValue::Ident(s) => {
-                    return Ok(LispExpr::Literal(Box::new(LispSymbol { value: s.clone() })));
-                }
+                    None => return Err(EvalError::BadSyntax("lambda")),
+                },
+                symbol::IF => return self.eval_if(rest, env),
+                symbol::COND => return self.eval_cond(rest, env),
+                symbol::BEGIN => return self.eval_body(rest.into_iter(), env.clone()),
                 _ => {}
             }
         }
 
-        Err(EvalError::AAAA)
-    }
-
-    pub fn evaluate_expr(&self, expr: LispExpr) -> Result<LispExpr, EvalError> {
-        match expr {
-            LispExpr::Literal(lit) => match lit.ltype() {
-                LispTypeId::List => {
-                    let val = lit.value();
-                    let mut list = val.downcast::<LispList>().unwrap().value.clone();
-
-                    if list.len() == 0 {
-                        Ok(LispExpr::Literal(lit))
-                    } else {
-                        if let LispExpr::Literal(clit) = list.remove(0) {
-                            let callee = clit.value().downcast::<LispSymbol>().unwrap();
-                            for func in &self.context.functions {
-                                match func {
-                                    LispFunction::Internal {
-                                        name,
-                                        args: _,
-                                        func,
-                                    } => {
-                                        if name.value == callee.value {
-                                            let mut args_eval = Vec::new();
-                                            for expr in list {
-                                                match self.evaluate_expr(expr) {
-                                                    Ok(expr) => args_eval.push(expr),
-                                                    Err(e) => return Err(e),
-                                                }
-                                            }
-                                            return func(&self.context, args_eval);
-                                        }
-                                    }
-                                    LispFunction::Lisp {
-                                        name,
-                                        args: _,
-                                        body: _,
-                                    } => {
-                                        if name.value == callee.value {
-                                            todo!("Have to handle call stack here somehow");
-                                        }
-                                    }
-                                }
-                            }
-                        }
-
-                        Ok(LispExpr::Null)
-                    }
+        let callee = self.evaluate_expr(pair.car.clone(), env)?;
+        let mut args = Vec::with_capacity(rest.len());
+        for expr in rest {
+            args.push(self.evaluate_expr(expr, env)?);
+        }
+        self.call(&callee, args)
+    }
+
+    /// Calls `callee`, which must evaluate to a function, with already
+    /// evaluated arguments.
+    pub fn apply(&self, callee: &LispExpr, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+        match self.call(callee, args)? {
+            Tail::Value(value) => Ok(value),
+            Tail::Eval(expr, env) => self.evaluate_expr(expr, &env),
+        }
+    }
+
+    /// Like `apply`, but leaves the last form of a Lisp function's body to
+    /// the caller.
+    fn call(&self, callee: &LispExpr, args: Vec<LispExpr>) -> Result<Tail, EvalError> {
+        let LispExpr::Function(func) = callee else {
+            return Err(EvalError::NotCallable(callee.ltype()));
+        };
+
+        match func.as_ref() {
+            // Builtins like `map` can call back into Lisp from native code, and
+            // compiled closures re-enter the VM, so both nest a level like a
+            // tree-walked call does
+            LispFunction::Internal { params, func, .. } => {
+                params.check_arity(args.len())?;
+                Ok(Tail::Value(self.nested(|| func(self, args))?))
+            }
+            LispFunction::Lisp {
+                args: params,
+                body,
+                defines,
+                env,
+                ..
+            } => {
+                let scope = Env::new(Self::bind_params(params, args)?, Some(env.clone()));
+                // Like compiled locals, a name the body defines shadows any
+                // outer binding from the start of the call
+                for &name in defines {
+                    scope.declare(name);
                 }
-                LispTypeId::Symbol => {
-                    let val = lit.value();
-                    let sym = val.downcast::<LispSymbol>().unwrap().value.clone();
-                    for var in &self.context.variables {
-                        if var.name == sym {
-                            return Ok(var.value.clone());
-                        }
-                    }
-                    Err(EvalError::AAAA)
+                self.eval_body(body.iter(), scope)
+            }
+            LispFunction::Compiled { proto, frame } => {
+                Ok(Tail::Value(self.nested(|| {
+                    Vm::new(self).call_closure(proto, frame, args)
+                })?))
+            }
+        }
+    }
+
+    /// Runs `f` one level deeper, failing if that exceeds the max depth.
+    fn nested<T>(&self, f: impl FnOnce() -> Result<T, EvalError>) -> Result<T, EvalError> {
+        let depth = self.depth.get();
+        if depth >= self.max_depth {
+            return Err(EvalError::StackOverflow {
+                backtrace: Vec::new(),
+            });
+        }
+
+        self.depth.set(depth + 1);
+        let result = f();
+        self.depth.set(depth);
+        result
+    }
+
+    /// Evaluates every form of `body` but the last, which is returned to be
+    /// evaluated in tail position.
+    fn eval_body(
+        &self,
+        body: impl Iterator<Item = LispExpr>,
+        env: Rc<Env>,
+    ) -> Result<Tail, EvalError> {
+        let mut body = body.peekable();
+        while let Some(expr) = body.next() {
+            if body.peek().is_none() {
+                return Ok(Tail::Eval(expr, env));
+            }
+            self.evaluate_expr(expr, &env)?;
+        }
+        Ok(Tail::Value(LispExpr::Null))
+    }
+
+    /// `(if test then else?)`
+    fn eval_if(&self, rest: Vec<LispExpr>, env: &Rc<Env>) -> Result<Tail, EvalError> {
+        let mut rest = rest.into_iter();
+        let (Some(test), Some(then), otherwise, None) =
+            (rest.next(), rest.next(), rest.next(), rest.next())
+        else {
+            return Err(EvalError::BadSyntax("if"));
+        };
+
+        if self.evaluate_expr(test, env)?.is_truthy() {
+            Ok(Tail::Eval(then, env.clone()))
+        } else {
+            Ok(Tail::Eval(otherwise.unwrap_or(LispExpr::Null), env.clone()))
+        }
+    }
+
+    /// `(cond (test body...)...)`, where a clause without a body yields the
+    /// value of its test and `else` matches unconditionally.
+    fn eval_cond(&self, rest: Vec<LispExpr>, env: &Rc<Env>) -> Result<Tail, EvalError> {
+        for clause in rest {
+            let LispExpr::Pair(clause) = clause else {
+                return Err(EvalError::BadSyntax("cond"));
+            };
+
+            let test = match clause.car {
+                LispExpr::Symbol(symbol::ELSE) => LispExpr::Symbol(symbol::T),
+                ref test => self.evaluate_expr(test.clone(), env)?,
+            };
+            if test.is_truthy() {
+                return match clause.cdr {
+                    LispExpr::Null => Ok(Tail::Value(test)),
+                    ref body => self.eval_body(body.list_to_vec()?.into_iter(), env.clone()),
+                };
+            }
+        }
+        Ok(Tail::Value(LispExpr::Null))
+    }
+
+    fn bind_params(
+        params: &LispExpr,
+        args: Vec<LispExpr>,
+    ) -> Result<Vec<(Symbol, LispExpr)>, EvalError> {
+        // `make_lambda` has already checked the shape of the lambda list
+        let Some(params) = Self::parse_params(params) else {
+            return Err(EvalError::BadSyntax("lambda"));
+        };
+        let values = bind_args(
+            params.required.len(),
+            params.optional.len(),
+            params.rest.is_some(),
+            &params.keys,
+            args,
+        )?;
+        Ok(params.names().zip(values).collect())
+    }
+
+    fn parse_params(params: &LispExpr) -> Option<Params> {
+        let params = params
+            .iter()
+            .map(|param| match param {
+                LispExpr::Symbol(sym) => Some(sym),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Params::parse(&params)
+    }
+
+    fn eval_quote(rest: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+        match rest.as_slice() {
+            [quoted] => Ok(quoted.clone()),
+            _ => Err(EvalError::BadSyntax("quote")),
+        }
+    }
+
+    /// `(define name value)` or `(define (name params...) body...)`, binding
+    /// `name` in the current scope.
+    fn eval_define(&self, rest: Vec<LispExpr>, env: &Rc<Env>) -> Result<LispExpr, EvalError> {
+        let (name, value) = match rest.as_slice() {
+            [LispExpr::Symbol(name), value] => (*name, self.evaluate_expr(value.clone(), env)?),
+            [LispExpr::Pair(signature), body @ ..] => match &signature.car {
+                LispExpr::Symbol(name) => {
+                    (*name, Self::make_lambda(*name, &signature.cdr, body, env)?)
                 }
-                _ => Ok(LispExpr::Literal(lit)),
+                _ => return Err(EvalError::BadSyntax("define")),
             },
-            _ => Ok(LispExpr::Null),
+            _ => return Err(EvalError::BadSyntax("define")),
+        };
+
+        env.define(name, value);
+        Ok(LispExpr::Symbol(name))
+    }
+
+    /// Creates a closure over `env`, after checking that `params` is a list
+    /// of symbols, optionally followed by `&optional` and the names of
+    /// optional parameters, then by `&rest name`, and then by `&key` and the
+    /// names of keyword parameters.
+    fn make_lambda(
+        name: Symbol,
+        params: &LispExpr,
+        body: &[LispExpr],
+        env: &Rc<Env>,
+    ) -> Result<LispExpr, EvalError> {
+        params.list_to_vec()?;
+        if Self::parse_params(params).is_none() {
+            return Err(EvalError::BadSyntax("lambda"));
         }
+
+        let mut defines = Vec::new();
+        collect_defines(body, &mut defines);
+        Ok(LispExpr::Function(Rc::new(LispFunction::Lisp {
+            name,
+            args: params.clone(),
+            body: LispExpr::list(body.to_vec()),
+            defines,
+            env: env.clone(),
+        })))
     }
 
-    pub fn run(&mut self) -> Result<(), EvalError> {
-        while let Some(_) = self.peek() {
-            match self.try_interpret_next() {
-                Ok(expr) => match self.evaluate_expr(expr) {
-                    Ok(_) => {}
-                    Err(e) => return Err(e),
-                },
-                Err(e) => return Err(e),
-            }
+    /// Evaluates top-level forms in order, pulling each from `forms` only
+    /// once the previous one has finished, so they can be parsed lazily.
+    /// Passing `&mut` an iterator leaves the forms after an error in it, to
+    /// be run by a later call once more fuel or time has been given.
+    pub fn run(&mut self, forms: impl IntoIterator<Item = ASTNode>) -> Result<(), EvalError> {
+        self.resume()?;
+        for node in forms {
+            self.eval_toplevel(node)?;
         }
 
         Ok(())
     }
+
+    /// Evaluates one top-level form with the current backend, or on the VM
+    /// if it may have to be paused.
+    fn eval_toplevel(&mut self, node: ASTNode) -> Result<LispExpr, EvalError> {
+        if self.backend == Backend::Bytecode || self.is_limited() {
+            let proto = Compiler::compile(&node)?;
+            self.run_vm(|vm| vm.run(proto))
+        } else {
+            self.evaluate_expr(Self::node_to_expr(node), &self.context.globals)
+        }
+    }
+
+    /// Runs top-level forms that were compiled ahead of time, e.g. loaded
+    /// from a `.lispc` module, on the VM regardless of the backend.
+    pub fn run_compiled(
+        &mut self,
+        forms: impl IntoIterator<Item = Rc<Prototype>>,
+    ) -> Result<(), EvalError> {
+        self.resume()?;
+        for proto in forms {
+            self.run_vm(|vm| vm.run(proto))?;
+        }
+
+        Ok(())
+    }
+
+    /// Carries on with the form paused by running out of fuel or time, if
+    /// any.
+    fn resume(&mut self) -> Result<(), EvalError> {
+        match self.paused.take() {
+            Some(paused) => self.run_vm(|vm| vm.resume(paused)).map(|_| ()),
+            None => Ok(()),
+        }
+    }
+
+    /// Runs a top-level form on a fresh VM, keeping its state if it pauses.
+    fn run_vm(
+        &mut self,
+        f: impl FnOnce(&mut Vm) -> Result<LispExpr, EvalError>,
+    ) -> Result<LispExpr, EvalError> {
+        let mut vm = Vm::new(self);
+        let result = f(&mut vm);
+        self.paused = vm.into_paused();
+
+        match result {
+            Err(e @ (EvalError::OutOfFuel | EvalError::Timeout)) if self.paused.is_none() => {
+                Err(EvalError::Unresumable(Box::new(e)))
+            }
+            result => result,
+        }
+    }
+}
+
+/// Collects the names that `body` defines outside of nested lambdas, as the
+/// compiler does for the locals of a compiled function.
+fn collect_defines(body: &[LispExpr], names: &mut Vec<Symbol>) {
+    for expr in body {
+        let LispExpr::Pair(pair) = expr else {
+            continue;
+        };
+        let Ok(items) = expr.list_to_vec() else {
+            continue;
+        };
+        match pair.car {
+            LispExpr::Symbol(symbol::QUOTE | symbol::LAMBDA) => {}
+            LispExpr::Symbol(symbol::DEFINE) => {
+                let name = match items.get(1) {
+                    Some(LispExpr::Symbol(name)) => Some(*name),
+                    Some(LispExpr::Pair(signature)) => match signature.car {
+                        LispExpr::Symbol(name) => Some(name),
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                if let Some(name) = name
+                    && !names.contains(&name)
+                {
+                    names.push(name);
+                }
+                // The value of `(define name value)` may define more names
+                if let [_, LispExpr::Symbol(_), value @ ..] = items.as_slice() {
+                    collect_defines(value, names);
+                }
+            }
+            _ => collect_defines(&items, names),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::ast::ASTParser;
+    use crate::token::Tokenizer;
+
+    /// Evaluates `source` with `backend` on a thread with `stack_size` bytes
+    /// of stack, and a max depth to match, returning the printed value of
+    /// the last form.
+    fn eval_on_stack(
+        source: impl Into<String>,
+        backend: Backend,
+        stack_size: usize,
+    ) -> Result<String, String> {
+        let source = source.into();
+        thread::Builder::new()
+            .stack_size(stack_size)
+            .spawn(move || {
+                let mut eval = Evaluator::new();
+                eval.set_backend(backend);
+                eval.set_stack_size(stack_size);
+                let mut last = LispExpr::Null;
+                for node in ASTParser::new(Tokenizer::new(&source)) {
+                    let node = node.map_err(|e| format!("{:?}", e))?;
+                    last = eval.eval_toplevel(node).map_err(|e| format!("{:?}", e))?;
+                }
+                Ok(expr_to_string(&last).unwrap())
+            })
+            .unwrap()
+            .join()
+            .unwrap()
+    }
+
+    fn eval_on_small_stack(source: &'static str, backend: Backend) -> Result<String, String> {
+        eval_on_stack(source, backend, 256 * 1024)
+    }
+
+    fn assert_evals_to(source: &'static str, expected: &str) {
+        for backend in [Backend::TreeWalk, Backend::Bytecode] {
+            let result = eval_on_small_stack(source, backend);
+            assert_eq!(result.as_deref(), Ok(expected), "{:?}", backend);
+        }
+    }
+
+    #[test]
+    fn million_iteration_tail_loop_runs_in_constant_stack() {
+        assert_evals_to(
+            "(define (count n acc) (if (= n 0) acc (count (- n 1) (+ acc 1))))
+             (count 1000000 0)",
+            "1000000",
+        );
+    }
+
+    #[test]
+    fn if_branches_are_tail_positions() {
+        assert_evals_to(
+            "(define (loop n) (if (> n 0) (loop (- n 1)) 'done))
+             (loop 100000)",
+            "done",
+        );
+    }
+
+    #[test]
+    fn cond_clause_bodies_are_tail_positions() {
+        assert_evals_to(
+            "(define (loop n)
+               (cond ((= n 0) 'done)
+                     ((= (mod n 2) 0) (loop (- n 1)))
+                     (else 'odd (loop (- n 1)))))
+             (loop 100000)",
+            "done",
+        );
+    }
+
+    #[test]
+    fn last_form_of_begin_is_a_tail_position() {
+        assert_evals_to(
+            "(define (loop n) (begin 'ignored (if (= n 0) 'done (loop (- n 1)))))
+             (loop 100000)",
+            "done",
+        );
+    }
+
+    #[test]
+    fn mutual_tail_calls_run_in_constant_stack() {
+        assert_evals_to(
+            "(define (even? n) (if (= n 0) t (odd? (- n 1))))
+             (define (odd? n) (if (= n 0) nil (even? (- n 1))))
+             (even? 100000)",
+            "t",
+        );
+    }
+
+    /// Even in unoptimized builds, the max depth for a stack has to fail
+    /// cleanly before the stack runs out.
+    fn assert_overflows_cleanly(source: &'static str, backends: &[Backend]) {
+        for &backend in backends {
+            let result = eval_on_stack(source, backend, 2 * 1024 * 1024);
+            assert!(
+                result
+                    .as_ref()
+                    .is_err_and(|e| e.starts_with("StackOverflow")),
+                "{:?}: {:?}",
+                backend,
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn deep_recursion_overflows_cleanly() {
+        assert_overflows_cleanly(
+            "(define (f n) (if (= n 0) 0 (+ 1 (f (- n 1)))))
+             (f 100000)",
+            &[Backend::TreeWalk],
+        );
+    }
+
+    #[test]
+    fn deep_recursion_through_builtins_overflows_cleanly() {
+        let backends = [Backend::TreeWalk, Backend::Bytecode];
+        assert_overflows_cleanly(
+            "(define (f n) (if (= n 0) 0 (car (map (lambda (x) (+ x (f (- n 1)))) '(1)))))
+             (f 100000)",
+            &backends,
+        );
+        assert_overflows_cleanly(
+            "(define (f n) (if (= n 0) 0 (car (sort (list 2 1) (lambda (a b) (f (- n 1)))))))
+             (f 100000)",
+            &backends,
+        );
+    }
+
+    #[test]
+    fn ordinary_recursion_fits_the_default_depth() {
+        for backend in [Backend::TreeWalk, Backend::Bytecode] {
+            let result = eval_on_stack(
+                "(define (range n) (if (= n 0) nil (cons n (range (- n 1)))))
+                 (define (len l) (if l (+ 1 (len (cdr l))) 0))
+                 (len (range 5000))",
+                backend,
+                STACK_SIZE,
+            );
+            assert_eq!(result.as_deref(), Ok("5000"), "{:?}", backend);
+        }
+    }
+
+    #[test]
+    fn compiled_calls_nest_on_the_heap() {
+        let result = eval_on_small_stack(
+            "(define (f n) (if (= n 0) 0 (+ 1 (f (- n 1)))))
+             (f 100000)",
+            Backend::Bytecode,
+        );
+        assert_eq!(result.as_deref(), Ok("100000"));
+    }
+
+    #[test]
+    fn compiled_calls_overflow_at_max_frames() {
+        let source = "(define (f n) (if (= n 0) 0 (+ 1 (f (- n 1)))))
+                      (f 5000)";
+        let mut eval = Evaluator::new();
+        eval.set_backend(Backend::Bytecode);
+        eval.set_max_frames(1000);
+        let result = eval.run(ASTParser::new(Tokenizer::new(source)).map(Result::unwrap));
+        assert!(matches!(result, Err(EvalError::StackOverflow { .. })));
+    }
+
+    #[test]
+    fn deeply_nested_lists_print_and_compare() {
+        assert_evals_to(
+            "(define (nest n acc) (if (= n 0) acc (nest (- n 1) (list acc))))
+             (define a (nest 100000 'x))
+             (define b (nest 100000 'x))
+             (list (equal a b) (string-length (string a)))",
+            "(t 200001)",
+        );
+    }
+
+    #[test]
+    fn deeply_nested_source_is_rejected() {
+        let source = format!("'{}{}", "(".repeat(1000), ")".repeat(1000));
+        for backend in [Backend::TreeWalk, Backend::Bytecode] {
+            let result = eval_on_stack(source.clone(), backend, 2 * 1024 * 1024);
+            assert!(
+                result.as_ref().is_err_and(|e| e.contains("TooDeep")),
+                "{:?}: {:?}",
+                backend,
+                result
+            );
+        }
+    }
+
+    fn global(eval: &Evaluator, name: &str) -> Option<String> {
+        let value = eval.context.globals.get(Symbol::intern(name))?;
+        Some(expr_to_string(&value).unwrap())
+    }
+
+    #[test]
+    fn refuelled_run_resumes_a_paused_form() {
+        let source = "(define hits 0)
+                      (define (spin n acc) (if (= n 0) acc (spin (- n 1) (+ acc 1))))
+                      (begin
+                        (define hits (+ hits 1))
+                        (define result (spin 20000 0)))
+                      (define after result)";
+        for backend in [Backend::TreeWalk, Backend::Bytecode] {
+            let mut eval = Evaluator::new();
+            eval.set_backend(backend);
+            eval.set_fuel(Some(100));
+            let mut forms = ASTParser::new(Tokenizer::new(source)).map(Result::unwrap);
+
+            // Far less fuel per slice than the long form needs in total
+            let mut pauses = 0;
+            loop {
+                match eval.run(&mut forms) {
+                    Ok(()) => break,
+                    Err(EvalError::OutOfFuel) => eval.add_fuel(100),
+                    Err(e) => panic!("{:?}: {:?}", backend, e),
+                }
+                pauses += 1;
+            }
+
+            assert!(pauses > 100, "{:?}", backend);
+            assert_eq!(global(&eval, "hits").as_deref(), Some("1"), "{:?}", backend);
+            assert_eq!(
+                global(&eval, "after").as_deref(),
+                Some("20000"),
+                "{:?}",
+                backend
+            );
+        }
+    }
+
+    #[test]
+    fn running_out_inside_a_callback_abandons_the_form() {
+        let source = "(define (spin n acc) (if (= n 0) acc (spin (- n 1) (+ acc 1))))
+                      (define result (car (map (lambda (x) (spin 20000 0)) '(1))))
+                      (define after 'next)";
+        for backend in [Backend::TreeWalk, Backend::Bytecode] {
+            let mut eval = Evaluator::new();
+            eval.set_backend(backend);
+            eval.set_fuel(Some(1000));
+            let mut forms = ASTParser::new(Tokenizer::new(source)).map(Result::unwrap);
+
+            let result = eval.run(&mut forms);
+            assert!(
+                matches!(&result, Err(EvalError::Unresumable(e)) if matches!(**e, EvalError::OutOfFuel)),
+                "{:?}: {:?}",
+                backend,
+                result
+            );
+            eval.add_fuel(1_000_000);
+            eval.run(&mut forms).unwrap();
+
+            assert_eq!(global(&eval, "result"), None, "{:?}", backend);
+            assert_eq!(
+                global(&eval, "after").as_deref(),
+                Some("next"),
+                "{:?}",
+                backend
+            );
+        }
+    }
+
+    #[test]
+    fn internal_define_read_before_it_runs_is_unbound() {
+        for backend in [Backend::TreeWalk, Backend::Bytecode] {
+            let result = eval_on_small_stack(
+                "(define (early) (define x y) (define y 5) y)
+                 (early)",
+                backend,
+            );
+            assert_eq!(
+                result,
+                Err("UnboundVariable(\"y\")".to_string()),
+                "{:?}",
+                backend
+            );
+        }
+    }
+
+    #[test]
+    fn internal_define_shadows_a_global_before_it_runs() {
+        for source in [
+            "(define x 10)
+             (define (early) (define y x) (define x 1) y)
+             (early)",
+            "(define x 10)
+             (define (maybe flag) (if flag (define x 1) nil) x)
+             (maybe nil)",
+        ] {
+            for backend in [Backend::TreeWalk, Backend::Bytecode] {
+                assert_eq!(
+                    eval_on_small_stack(source, backend),
+                    Err("UnboundVariable(\"x\")".to_string()),
+                    "{:?}",
+                    backend
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn empty_else_clause_yields_true() {
+        assert_evals_to("(cond (nil 1) (else))", "t");
+    }
+
+    #[test]
+    fn optional_parameters_default_to_nil() {
+        assert_evals_to(
+            "(define (f a &optional b c &rest more) (list a b c more))
+             (list (f 1) (f 1 2) (f 1 2 3 4 5))",
+            "((1 nil nil nil) (1 2 nil nil) (1 2 3 (4 5)))",
+        );
+        assert_evals_to(
+            "(define (g &optional x &key y) (list x y))
+             (list (g) (g 1 :y 2))",
+            "((nil nil) (1 2))",
+        );
+    }
+
+    #[test]
+    fn arity_is_checked_against_the_lambda_list() {
+        let cases = [
+            ("((lambda (a &optional b) a) 1 2 3)", 2, 3),
+            ("((lambda (a &optional b) a))", 1, 0),
+            ("(number->string 1 2 3)", 2, 3),
+            ("(substring \"abc\")", 2, 1),
+            ("(car)", 1, 0),
+            ("(cons 1 2 3)", 2, 3),
+            ("(apply list)", 2, 1),
+            ("(random-float 1)", 0, 1),
+        ];
+        for (source, expected, found) in cases {
+            for backend in [Backend::TreeWalk, Backend::Bytecode] {
+                assert_eq!(
+                    eval_on_small_stack(source, backend),
+                    Err(format!(
+                        "ArityMismatch {{ expected: {}, found: {} }}",
+                        expected, found
+                    )),
+                    "{} {:?}",
+                    source,
+                    backend
+                );
+            }
+        }
+    }
+
+    /// Draws a value from each random builtin, printed as a list.
+    fn draw(eval: &mut Evaluator) -> String {
+        let source = "(define drawn
+                        (list (random 1000000)
+                              (random 100000000000000000000)
+                              (random 1.5)
+                              (random-float)
+                              (shuffle '(1 2 3 4 5 6 7 8))))";
+        eval.run(ASTParser::new(Tokenizer::new(source)).map(Result::unwrap))
+            .unwrap();
+        global(eval, "drawn").unwrap()
+    }
+
+    #[test]
+    fn seeded_evaluators_draw_the_same_values() {
+        let seeded = |seed| {
+            let mut eval = Evaluator::new();
+            eval.set_random_seed(seed);
+            eval
+        };
+        let (mut a, mut b, mut c) = (seeded(42), seeded(42), seeded(42));
+
+        let first = draw(&mut a);
+        assert_eq!(draw(&mut b), first);
+        assert_eq!(draw(&mut c), first);
+
+        // Reseeding one evaluator leaves the others' sequences alone
+        b.set_random_seed(7);
+        let second = draw(&mut a);
+        assert_eq!(draw(&mut c), second);
+        assert_ne!(draw(&mut b), second);
+
+        // Seeding from Lisp is the same as seeding from Rust
+        let mut d = Evaluator::new();
+        d.run(ASTParser::new(Tokenizer::new("(set-random-seed! 42)")).map(Result::unwrap))
+            .unwrap();
+        assert_eq!(draw(&mut d), first);
+    }
 }
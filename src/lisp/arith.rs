@@ -0,0 +1,212 @@
+use std::cmp::Ordering;
+
+use crate::{
+    interpreter::{EvalError, Evaluator},
+    lisp::{bigint::BigInt, internal::*, number::LispNumber, ratio::Ratio},
+};
+
+pub(crate) fn expect_number(expr: &LispExpr) -> Result<&LispNumber, EvalError> {
+    match expr {
+        LispExpr::Number(n) => Ok(n),
+        expr => Err(expr.mismatch(LispTypeId::Number)),
+    }
+}
+
+/// Combines `init` with each of `args` in turn, left to right.
+fn fold_numbers(
+    init: LispNumber,
+    args: &[LispExpr],
+    op: impl Fn(&LispNumber, &LispNumber) -> Result<LispNumber, EvalError>,
+) -> Result<LispExpr, EvalError> {
+    let mut acc = init;
+    for arg in args {
+        acc = op(&acc, expect_number(arg)?)?;
+    }
+    Ok(LispExpr::Number(acc))
+}
+
+pub fn lisp_add(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    fold_numbers(LispNumber::Integer(0), &args, |a, b| Ok(a.add(b)))
+}
+
+/// `(- x)` negates, `(- x y z)` subtracts `y` and `z` from `x`.
+pub fn lisp_sub(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let first = expect_number(&args[0])?;
+    if args.len() == 1 {
+        return Ok(LispExpr::Number(first.neg()));
+    }
+    fold_numbers(first.clone(), &args[1..], |a, b| Ok(a.sub(b)))
+}
+
+pub fn lisp_mul(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    fold_numbers(LispNumber::Integer(1), &args, |a, b| Ok(a.mul(b)))
+}
+
+/// `(/ x)` is the reciprocal, `(/ x y z)` divides `x` by `y` and then `z`.
+pub fn lisp_div(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let first = expect_number(&args[0])?;
+    if args.len() == 1 {
+        return Ok(LispExpr::Number(LispNumber::Integer(1).div(first)?));
+    }
+    fold_numbers(first.clone(), &args[1..], LispNumber::div)
+}
+
+/// Remainder with the sign of the divisor
+pub fn lisp_mod(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let n = expect_number(&args[0])?;
+    let divisor = expect_number(&args[1])?;
+    Ok(LispExpr::Number(n.remainder(divisor, true)?))
+}
+
+/// Remainder with the sign of the dividend
+pub fn lisp_rem(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let n = expect_number(&args[0])?;
+    let divisor = expect_number(&args[1])?;
+    Ok(LispExpr::Number(n.remainder(divisor, false)?))
+}
+
+/// Whether `holds` is true of the ordering of every adjacent pair of `args`.
+fn compare_chain(args: &[LispExpr], holds: fn(Ordering) -> bool) -> Result<LispExpr, EvalError> {
+    let nums = args
+        .iter()
+        .map(expect_number)
+        .collect::<Result<Vec<_>, _>>()?;
+    let ok = nums
+        .windows(2)
+        .all(|pair| pair[0].num_cmp(pair[1]).is_some_and(holds));
+    Ok(LispExpr::from_bool(ok))
+}
+
+pub fn lisp_num_eq(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    compare_chain(&args, Ordering::is_eq)
+}
+
+pub fn lisp_lt(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    compare_chain(&args, Ordering::is_lt)
+}
+
+pub fn lisp_le(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    compare_chain(&args, Ordering::is_le)
+}
+
+pub fn lisp_gt(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    compare_chain(&args, Ordering::is_gt)
+}
+
+pub fn lisp_ge(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    compare_chain(&args, Ordering::is_ge)
+}
+
+/// The argument that orders `wanted` against all others, inexact if any
+/// argument is.
+fn extremum(args: &[LispExpr], wanted: Ordering) -> Result<LispExpr, EvalError> {
+    let mut best = expect_number(&args[0])?;
+    let mut exact = best.is_exact();
+    for arg in &args[1..] {
+        let n = expect_number(arg)?;
+        exact &= n.is_exact();
+        if n.num_cmp(best) == Some(wanted) {
+            best = n;
+        }
+    }
+
+    Ok(LispExpr::Number(if exact {
+        best.clone()
+    } else {
+        LispNumber::Float(best.to_f64())
+    }))
+}
+
+pub fn lisp_min(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    extremum(&args, Ordering::Less)
+}
+
+pub fn lisp_max(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    extremum(&args, Ordering::Greater)
+}
+
+pub fn lisp_abs(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    Ok(LispExpr::Number(expect_number(&args[0])?.abs()))
+}
+
+pub fn lisp_numerator(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let n = expect_number(&args[0])?;
+    let Some(ratio) = n.to_ratio() else {
+        return Ok(args[0].clone());
+    };
+    let numer = LispNumber::from_big(ratio.numer().clone());
+    Ok(LispExpr::Number(if n.is_exact() {
+        numer
+    } else {
+        LispNumber::Float(numer.to_f64())
+    }))
+}
+
+pub fn lisp_denominator(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let n = expect_number(&args[0])?;
+    let Some(ratio) = n.to_ratio() else {
+        return Ok(LispExpr::Number(LispNumber::Float(1.0)));
+    };
+    let denom = LispNumber::from_big(ratio.denom().clone());
+    Ok(LispExpr::Number(if n.is_exact() {
+        denom
+    } else {
+        LispNumber::Float(denom.to_f64())
+    }))
+}
+
+pub fn lisp_exact_to_inexact(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let n = expect_number(&args[0])?;
+    Ok(LispExpr::Number(LispNumber::Float(n.to_f64())))
+}
+
+/// `(rationalize x y)`: the simplest rational within `y` of `x`, which is
+/// inexact if either argument is.
+pub fn lisp_rationalize(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let x = expect_number(&args[0])?;
+    let y = expect_number(&args[1])?;
+    let (Some(exact_x), Some(exact_y)) = (x.to_ratio(), y.to_ratio()) else {
+        // Anything is within an infinite distance of a finite number
+        let x = x.to_f64();
+        let n = if x.is_finite() { 0.0 } else { x };
+        return Ok(LispExpr::Number(LispNumber::Float(n)));
+    };
+
+    let delta = if exact_y < Ratio::from_integer(BigInt::from_i64(0)) {
+        exact_y.neg()
+    } else {
+        exact_y
+    };
+    let simplest = LispNumber::from_ratio(Ratio::simplest_between(
+        &exact_x.sub(&delta),
+        &exact_x.add(&delta),
+    ));
+
+    Ok(LispExpr::Number(if x.is_exact() && y.is_exact() {
+        simplest
+    } else {
+        LispNumber::Float(simplest.to_f64())
+    }))
+}
+
+/// An optional radix argument, between 2 and 36, defaulting to 10.
+pub(crate) fn expect_radix(expr: Option<&LispExpr>) -> Result<u32, EvalError> {
+    match expr {
+        None => Ok(10),
+        Some(LispExpr::Number(LispNumber::Integer(radix @ 2..=36))) => Ok(*radix as u32),
+        Some(LispExpr::Number(_)) => Err(EvalError::BadRadix),
+        Some(expr) => Err(expr.mismatch(LispTypeId::Number)),
+    }
+}
+
+pub fn lisp_number_to_string(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let radix = expect_radix(args.get(1))?;
+
+    match &args[0] {
+        LispExpr::Number(n) => match n.to_str_radix(radix) {
+            Some(s) => Ok(LispExpr::String(s.into())),
+            None => Err(EvalError::BadRadix),
+        },
+        expr => Err(expr.mismatch(LispTypeId::Number)),
+    }
+}
@@ -0,0 +1,463 @@
+use std::cmp::Ordering;
+use std::fmt::Display;
+
+/// An arbitrary-precision integer: a sign and a magnitude in little endian
+/// base 2^32 limbs. The magnitude never has high zero limbs, so zero has no
+/// limbs at all, and zero is never negative.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BigInt {
+    negative: bool,
+    limbs: Vec<u32>,
+}
+
+fn trim(limbs: &mut Vec<u32>) {
+    while limbs.last() == Some(&0) {
+        limbs.pop();
+    }
+}
+
+fn cmp_mag(a: &[u32], b: &[u32]) -> Ordering {
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (a, b) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut out = Vec::with_capacity(a.len() + 1);
+    let mut carry = 0;
+    for (i, &x) in a.iter().enumerate() {
+        let sum = x as u64 + b.get(i).copied().unwrap_or(0) as u64 + carry;
+        out.push(sum as u32);
+        carry = sum >> 32;
+    }
+    if carry > 0 {
+        out.push(carry as u32);
+    }
+    out
+}
+
+/// `a - b`, where `a` is at least as large as `b`
+fn sub_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut out = Vec::with_capacity(a.len());
+    let mut borrow = 0;
+    for (i, &x) in a.iter().enumerate() {
+        let (diff, under1) = x.overflowing_sub(b.get(i).copied().unwrap_or(0));
+        let (diff, under2) = diff.overflowing_sub(borrow);
+        out.push(diff);
+        borrow = (under1 || under2) as u32;
+    }
+    trim(&mut out);
+    out
+}
+
+fn mul_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut out = vec![0; a.len() + b.len()];
+    for (i, &x) in a.iter().enumerate() {
+        let mut carry = 0;
+        for (j, &y) in b.iter().enumerate() {
+            let t = x as u64 * y as u64 + out[i + j] as u64 + carry;
+            out[i + j] = t as u32;
+            carry = t >> 32;
+        }
+        out[i + b.len()] = carry as u32;
+    }
+    trim(&mut out);
+    out
+}
+
+/// Multiplies `limbs` by `factor` and adds `add`, in place.
+fn mul_add_small(limbs: &mut Vec<u32>, factor: u32, add: u32) {
+    let mut carry = add as u64;
+    for limb in limbs.iter_mut() {
+        let t = *limb as u64 * factor as u64 + carry;
+        *limb = t as u32;
+        carry = t >> 32;
+    }
+    if carry > 0 {
+        limbs.push(carry as u32);
+    }
+}
+
+/// Divides `limbs` by `divisor` in place and returns the remainder.
+fn div_small(limbs: &mut Vec<u32>, divisor: u32) -> u32 {
+    let mut rem = 0;
+    for limb in limbs.iter_mut().rev() {
+        let cur = (rem << 32) | *limb as u64;
+        *limb = (cur / divisor as u64) as u32;
+        rem = cur % divisor as u64;
+    }
+    trim(limbs);
+    rem as u32
+}
+
+/// Shifts `limbs` left by one bit, shifting `bit` in at the bottom.
+fn shl1(limbs: &mut Vec<u32>, bit: u32) {
+    let mut carry = bit;
+    for limb in limbs.iter_mut() {
+        let next = *limb >> 31;
+        *limb = (*limb << 1) | carry;
+        carry = next;
+    }
+    if carry != 0 {
+        limbs.push(carry);
+    }
+}
+
+/// Negates a two's complement number in place.
+fn negate_twos(limbs: &mut [u32]) {
+    let mut carry = true;
+    for limb in limbs.iter_mut() {
+        (*limb, carry) = (!*limb).overflowing_add(carry as u32);
+    }
+}
+
+fn div_rem_mag(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if cmp_mag(a, b) == Ordering::Less {
+        return (Vec::new(), a.to_vec());
+    }
+    if let [divisor] = b {
+        let mut quotient = a.to_vec();
+        let rem = div_small(&mut quotient, *divisor);
+        return (quotient, if rem == 0 { Vec::new() } else { vec![rem] });
+    }
+
+    // Binary long division: slow per bit, but simple and only needed for
+    // multi-limb divisors
+    let mut quotient = vec![0; a.len()];
+    let mut rem = Vec::new();
+    for i in (0..a.len() * 32).rev() {
+        shl1(&mut rem, (a[i / 32] >> (i % 32)) & 1);
+        if cmp_mag(&rem, b) != Ordering::Less {
+            rem = sub_mag(&rem, b);
+            quotient[i / 32] |= 1 << (i % 32);
+        }
+    }
+    trim(&mut quotient);
+    (quotient, rem)
+}
+
+impl BigInt {
+    fn from_parts(negative: bool, mut limbs: Vec<u32>) -> BigInt {
+        trim(&mut limbs);
+        BigInt {
+            negative: negative && !limbs.is_empty(),
+            limbs,
+        }
+    }
+
+    /// A non-negative integer from little endian base 2^32 limbs.
+    pub fn from_limbs(limbs: Vec<u32>) -> BigInt {
+        BigInt::from_parts(false, limbs)
+    }
+
+    pub fn from_i64(n: i64) -> BigInt {
+        let mag = n.unsigned_abs();
+        BigInt::from_parts(n < 0, vec![mag as u32, (mag >> 32) as u32])
+    }
+
+    /// `2^exponent`
+    pub fn pow2(exponent: u32) -> BigInt {
+        let mut limbs = vec![0; exponent as usize / 32 + 1];
+        limbs[exponent as usize / 32] = 1 << (exponent % 32);
+        BigInt::from_parts(false, limbs)
+    }
+
+    /// The value as an `i64`, if it fits in one.
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.limbs.len() > 2 {
+            return None;
+        }
+        let mag = self
+            .limbs
+            .iter()
+            .rev()
+            .fold(0, |acc, &limb| (acc << 32) | limb as u64);
+        if self.negative {
+            (mag <= 1 << 63).then(|| (mag as i64).wrapping_neg())
+        } else {
+            i64::try_from(mag).ok()
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        let mag = self
+            .limbs
+            .iter()
+            .rev()
+            .fold(0.0, |acc, &limb| acc * 4294967296.0 + limb as f64);
+        if self.negative { -mag } else { mag }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.limbs.is_empty()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    pub fn neg(&self) -> BigInt {
+        BigInt::from_parts(!self.negative, self.limbs.clone())
+    }
+
+    pub fn abs(&self) -> BigInt {
+        BigInt::from_parts(false, self.limbs.clone())
+    }
+
+    fn add_signed(&self, negative: bool, limbs: &[u32]) -> BigInt {
+        if self.negative == negative {
+            return BigInt::from_parts(negative, add_mag(&self.limbs, limbs));
+        }
+        match cmp_mag(&self.limbs, limbs) {
+            Ordering::Less => BigInt::from_parts(negative, sub_mag(limbs, &self.limbs)),
+            _ => BigInt::from_parts(self.negative, sub_mag(&self.limbs, limbs)),
+        }
+    }
+
+    pub fn add(&self, other: &BigInt) -> BigInt {
+        self.add_signed(other.negative, &other.limbs)
+    }
+
+    pub fn sub(&self, other: &BigInt) -> BigInt {
+        self.add_signed(!other.negative, &other.limbs)
+    }
+
+    pub fn mul(&self, other: &BigInt) -> BigInt {
+        BigInt::from_parts(
+            self.negative != other.negative,
+            mul_mag(&self.limbs, &other.limbs),
+        )
+    }
+
+    /// Truncating division, returning the quotient and a remainder with the
+    /// sign of `self`. Panics if `other` is zero.
+    pub fn div_rem(&self, other: &BigInt) -> (BigInt, BigInt) {
+        assert!(!other.is_zero(), "BigInt division by zero");
+        let (quotient, rem) = div_rem_mag(&self.limbs, &other.limbs);
+        (
+            BigInt::from_parts(self.negative != other.negative, quotient),
+            BigInt::from_parts(self.negative, rem),
+        )
+    }
+
+    /// Number of bits in the magnitude
+    pub fn bits(&self) -> u32 {
+        match self.limbs.last() {
+            Some(top) => (self.limbs.len() as u32 - 1) * 32 + (32 - top.leading_zeros()),
+            None => 0,
+        }
+    }
+
+    /// The largest integer whose square is at most `self`, which must not be
+    /// negative.
+    pub fn isqrt(&self) -> BigInt {
+        if self.is_zero() {
+            return BigInt::from_i64(0);
+        }
+
+        // Newton's method, starting above the root and falling towards it
+        let two = BigInt::from_i64(2);
+        let mut x = BigInt::pow2(self.bits() / 2 + 1);
+        loop {
+            let y = x.add(&self.div_rem(&x).0).div_rem(&two).0;
+            if y >= x {
+                return x;
+            }
+            x = y;
+        }
+    }
+
+    /// Greatest common divisor, which is never negative.
+    pub fn gcd(&self, other: &BigInt) -> BigInt {
+        let (mut a, mut b) = (self.abs(), other.abs());
+        while !b.is_zero() {
+            let (_, rem) = a.div_rem(&b);
+            a = b;
+            b = rem;
+        }
+        a
+    }
+
+    /// Two's complement limbs of the value, sign extended to `len` limbs.
+    fn to_twos(&self, len: usize) -> Vec<u32> {
+        let mut limbs = self.limbs.clone();
+        limbs.resize(len, 0);
+        if self.negative {
+            negate_twos(&mut limbs);
+        }
+        limbs
+    }
+
+    fn from_twos(mut limbs: Vec<u32>) -> BigInt {
+        let negative = limbs.last().is_some_and(|top| top >> 31 == 1);
+        if negative {
+            negate_twos(&mut limbs);
+        }
+        BigInt::from_parts(negative, limbs)
+    }
+
+    /// Applies `op` to each pair of limbs of the two's complement forms of
+    /// `self` and `other`, as if both were infinitely sign extended.
+    pub fn bitwise(&self, other: &BigInt, op: fn(u32, u32) -> u32) -> BigInt {
+        let len = self.limbs.len().max(other.limbs.len()) + 1;
+        let limbs = self
+            .to_twos(len)
+            .iter()
+            .zip(other.to_twos(len))
+            .map(|(a, b)| op(*a, b))
+            .collect();
+        BigInt::from_twos(limbs)
+    }
+
+    /// Parses unsigned `digits` in `radix`, which must be between 2 and 36.
+    pub fn parse_radix(digits: &str, radix: u32) -> Option<BigInt> {
+        if digits.is_empty() {
+            return None;
+        }
+        let mut limbs = Vec::new();
+        for c in digits.chars() {
+            mul_add_small(&mut limbs, radix, c.to_digit(radix)?);
+        }
+        Some(BigInt::from_parts(false, limbs))
+    }
+
+    /// Formats the value in `radix`, which must be between 2 and 36, using
+    /// lowercase digits.
+    pub fn to_str_radix(&self, radix: u32) -> String {
+        if self.is_zero() {
+            return "0".to_string();
+        }
+
+        // Peel off as many digits per division as fit in a limb
+        let mut width = 1;
+        let mut chunk = radix;
+        while let Some(next) = chunk.checked_mul(radix) {
+            chunk = next;
+            width += 1;
+        }
+
+        let mut limbs = self.limbs.clone();
+        let mut digits = Vec::new();
+        while !limbs.is_empty() {
+            let mut rem = div_small(&mut limbs, chunk);
+            for _ in 0..width {
+                digits.push(std::char::from_digit(rem % radix, radix).unwrap());
+                rem /= radix;
+                if limbs.is_empty() && rem == 0 {
+                    break;
+                }
+            }
+        }
+        if self.negative {
+            digits.push('-');
+        }
+        digits.iter().rev().collect()
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &BigInt) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_mag(&self.limbs, &other.limbs),
+            (true, true) => cmp_mag(&other.limbs, &self.limbs),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &BigInt) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Display for BigInt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_str_radix(10))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::lisp::number::LispNumber;
+
+    fn big(digits: &str) -> BigInt {
+        match digits.strip_prefix('-') {
+            Some(digits) => BigInt::parse_radix(digits, 10).unwrap().neg(),
+            None => BigInt::parse_radix(digits, 10).unwrap(),
+        }
+    }
+
+    #[test]
+    fn i64_bounds_convert_exactly() {
+        for n in [0, 1, -1, i64::MAX, i64::MIN, i64::MIN + 1] {
+            assert_eq!(BigInt::from_i64(n).to_i64(), Some(n));
+            assert_eq!(BigInt::from_i64(n).to_string(), n.to_string());
+        }
+        assert_eq!(big("9223372036854775808").to_i64(), None);
+        assert_eq!(big("-9223372036854775809").to_i64(), None);
+        assert_eq!(BigInt::from_i64(i64::MIN).neg(), BigInt::pow2(63));
+    }
+
+    #[test]
+    fn overflowing_integers_promote_and_demote() {
+        let min = LispNumber::Integer(i64::MIN);
+        let two_63 = LispNumber::Big(Rc::new(BigInt::pow2(63)));
+
+        assert_eq!(min.div(&LispNumber::Integer(-1)).unwrap(), two_63);
+        assert_eq!(min.abs(), two_63);
+        assert_eq!(min.neg(), two_63);
+        assert_eq!(
+            LispNumber::Integer(i64::MAX).add(&LispNumber::Integer(1)),
+            two_63
+        );
+        assert_eq!(
+            two_63.sub(&LispNumber::Integer(1)),
+            LispNumber::Integer(i64::MAX)
+        );
+        assert_eq!(two_63.neg(), min);
+        assert_eq!(
+            min.remainder(&LispNumber::Integer(-1), false).unwrap(),
+            LispNumber::Integer(0)
+        );
+    }
+
+    #[test]
+    fn div_rem_truncates_towards_zero() {
+        let cases = [
+            ("7", "2", "3", "1"),
+            ("-7", "2", "-3", "-1"),
+            ("7", "-2", "-3", "1"),
+            ("-7", "-2", "3", "-1"),
+            ("-6", "3", "-2", "0"),
+            ("-100000000000000000000", "7", "-14285714285714285714", "-2"),
+        ];
+        for (a, b, quotient, rem) in cases {
+            let (q, r) = big(a).div_rem(&big(b));
+            assert_eq!(
+                (q.to_string(), r.to_string()),
+                (quotient.into(), rem.into())
+            );
+        }
+    }
+
+    #[test]
+    fn radix_strings_round_trip() {
+        let n = big("-123456789012345678901234567890");
+        for radix in [2, 8, 10, 16, 36] {
+            let digits = n.abs().to_str_radix(radix);
+            assert_eq!(BigInt::parse_radix(&digits, radix).unwrap(), n.abs());
+            assert_eq!(n.to_str_radix(radix), format!("-{}", digits));
+        }
+        assert_eq!(BigInt::pow2(64).to_str_radix(16), "10000000000000000");
+        assert_eq!(BigInt::from_i64(0).to_str_radix(2), "0");
+        assert_eq!(BigInt::parse_radix("zz", 36), Some(BigInt::from_i64(1295)));
+        assert_eq!(BigInt::parse_radix("12", 2), None);
+        assert_eq!(BigInt::parse_radix("", 10), None);
+    }
+}
@@ -0,0 +1,57 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::lisp::{internal::LispExpr, symbol::Symbol};
+
+/// A scope of variable bindings.
+///
+/// The interpreter is a Lisp-1: functions and values share one namespace,
+/// so builtins are ordinary variables bound to function values in the
+/// global scope, `(define (f) ...)` and `(define f (lambda () ...))` are
+/// equivalent, and any expression evaluating to a function can be called.
+/// Each call to a `lambda` gets a new scope whose parent is the scope the
+/// `lambda` was created in.
+///
+/// A name declared but not yet defined is unassigned: it shadows any binding
+/// in a parent scope, but reading it fails until it is defined.
+pub struct Env {
+    vars: RefCell<HashMap<Symbol, Option<LispExpr>>>,
+    parent: Option<Rc<Env>>,
+}
+
+impl Env {
+    pub fn new(
+        vars: impl IntoIterator<Item = (Symbol, LispExpr)>,
+        parent: Option<Rc<Env>>,
+    ) -> Rc<Env> {
+        let vars = vars
+            .into_iter()
+            .map(|(sym, value)| (sym, Some(value)))
+            .collect();
+        Rc::new(Env {
+            vars: RefCell::new(vars),
+            parent,
+        })
+    }
+
+    pub fn get(&self, sym: Symbol) -> Option<LispExpr> {
+        let mut env = self;
+        loop {
+            if let Some(slot) = env.vars.borrow().get(&sym) {
+                return slot.clone();
+            }
+            env = env.parent.as_deref()?;
+        }
+    }
+
+    /// Binds `sym` in this scope, shadowing any binding in a parent scope.
+    pub fn define(&self, sym: Symbol, value: LispExpr) {
+        self.vars.borrow_mut().insert(sym, Some(value));
+    }
+
+    /// Binds `sym` in this scope as unassigned, unless it is already bound.
+    pub fn declare(&self, sym: Symbol) {
+        self.vars.borrow_mut().entry(sym).or_insert(None);
+    }
+}
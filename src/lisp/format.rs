@@ -0,0 +1,397 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::{
+    interpreter::{EvalError, Evaluator},
+    lisp::{
+        arith::expect_number,
+        function::{expr_to_readable, expr_to_string},
+        internal::*,
+        list::expect_list,
+        number::LispNumber,
+        string::expect_string,
+    },
+};
+
+/// A parsed piece of a `format` control string.
+enum Directive {
+    Text(String),
+    /// `~a`, or `~s` if `readable`, padded to `mincol` characters on the
+    /// right, or on the left with `@`
+    Print {
+        readable: bool,
+        mincol: usize,
+        pad_left: bool,
+    },
+    /// `~d`, padded on the left to `mincol` characters
+    Decimal {
+        mincol: usize,
+    },
+    /// `~w,df`, with `d` digits after the point, padded on the left to `w`
+    /// characters
+    Fixed {
+        width: usize,
+        digits: Option<usize>,
+    },
+    /// `~n%`
+    Newline(usize),
+    /// `~{...~}` runs its body over the elements of a list argument
+    Iterate(Vec<Directive>),
+    /// `~[a~;b~]` runs the clause picked by an integer argument, or with
+    /// `~:[` the first clause for nil and the second for anything else
+    Select {
+        clauses: Vec<Vec<Directive>>,
+        boolean: bool,
+    },
+    /// `~^` stops the enclosing iteration, or the whole string, once the
+    /// arguments run out
+    Escape,
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl Parser<'_> {
+    /// Parses up to the end of the string, or up to one of `~}`, `~]` or
+    /// `~;`, which is returned.
+    fn parse(&mut self) -> Result<(Vec<Directive>, Option<char>), EvalError> {
+        let mut directives = Vec::new();
+        let mut text = String::new();
+        while let Some(c) = self.chars.next() {
+            if c != '~' {
+                text.push(c);
+                continue;
+            }
+
+            let params = self.params()?;
+            let param = |i: usize| params.get(i).copied().flatten();
+            let (mut colon, mut at) = (false, false);
+            while let Some(&c @ (':' | '@')) = self.chars.peek() {
+                colon |= c == ':';
+                at |= c == '@';
+                self.chars.next();
+            }
+
+            let Some(c) = self.chars.next() else {
+                return Err(EvalError::BadFormat("unfinished directive"));
+            };
+            let directive = match c.to_ascii_lowercase() {
+                '~' => {
+                    text.push('~');
+                    continue;
+                }
+                c @ ('a' | 's') => Directive::Print {
+                    readable: c == 's',
+                    mincol: param(0).unwrap_or(0),
+                    pad_left: at,
+                },
+                'd' => Directive::Decimal {
+                    mincol: param(0).unwrap_or(0),
+                },
+                'f' => Directive::Fixed {
+                    width: param(0).unwrap_or(0),
+                    digits: param(1),
+                },
+                '%' => Directive::Newline(param(0).unwrap_or(1)),
+                '^' => Directive::Escape,
+                '{' => match self.parse()? {
+                    (body, Some('}')) => Directive::Iterate(body),
+                    _ => return Err(EvalError::BadFormat("unclosed ~{")),
+                },
+                '[' => {
+                    let mut clauses = Vec::new();
+                    loop {
+                        let (clause, end) = self.parse()?;
+                        clauses.push(clause);
+                        match end {
+                            Some(';') => continue,
+                            Some(']') => break,
+                            _ => return Err(EvalError::BadFormat("unclosed ~[")),
+                        }
+                    }
+                    if colon && clauses.len() != 2 {
+                        return Err(EvalError::BadFormat("~:[ needs two clauses"));
+                    }
+                    Directive::Select {
+                        clauses,
+                        boolean: colon,
+                    }
+                }
+                c @ ('}' | ']' | ';') => {
+                    if !text.is_empty() {
+                        directives.push(Directive::Text(text));
+                    }
+                    return Ok((directives, Some(c)));
+                }
+                _ => return Err(EvalError::BadFormat("unknown directive")),
+            };
+
+            if !text.is_empty() {
+                directives.push(Directive::Text(std::mem::take(&mut text)));
+            }
+            directives.push(directive);
+        }
+
+        if !text.is_empty() {
+            directives.push(Directive::Text(text));
+        }
+        Ok((directives, None))
+    }
+
+    /// Comma-separated numeric parameters, any of which may be left out.
+    fn params(&mut self) -> Result<Vec<Option<usize>>, EvalError> {
+        let mut params = Vec::new();
+        loop {
+            let mut digits = String::new();
+            while let Some(&c) = self.chars.peek()
+                && c.is_ascii_digit()
+            {
+                digits.push(c);
+                self.chars.next();
+            }
+            let param = match digits.as_str() {
+                "" => None,
+                digits => Some(
+                    digits
+                        .parse()
+                        .map_err(|_| EvalError::BadFormat("parameter too large"))?,
+                ),
+            };
+
+            if self.chars.peek() != Some(&',') {
+                if param.is_some() || !params.is_empty() {
+                    params.push(param);
+                }
+                return Ok(params);
+            }
+            self.chars.next();
+            params.push(param);
+        }
+    }
+}
+
+/// The arguments left to format.
+struct Args {
+    items: std::vec::IntoIter<LispExpr>,
+}
+
+impl Args {
+    fn next(&mut self) -> Result<LispExpr, EvalError> {
+        self.items
+            .next()
+            .ok_or(EvalError::BadFormat("not enough arguments"))
+    }
+
+    fn remaining(&self) -> usize {
+        self.items.len()
+    }
+}
+
+/// Pads `s` with spaces to at least `mincol` characters.
+fn pad(s: String, mincol: usize, pad_left: bool) -> String {
+    let fill = " ".repeat(mincol.saturating_sub(s.chars().count()));
+    if pad_left { fill + &s } else { s + &fill }
+}
+
+/// Runs `directives` into `out`, returning whether a `~^` stopped them.
+fn run(directives: &[Directive], args: &mut Args, out: &mut String) -> Result<bool, EvalError> {
+    for directive in directives {
+        match directive {
+            Directive::Text(text) => out.push_str(text),
+            Directive::Print {
+                readable,
+                mincol,
+                pad_left,
+            } => {
+                let arg = args.next()?;
+                let s = if *readable {
+                    expr_to_readable(&arg)?
+                } else {
+                    expr_to_string(&arg)?
+                };
+                out.push_str(&pad(s, *mincol, *pad_left));
+            }
+            // Like `~a` for anything but an integer
+            Directive::Decimal { mincol } => {
+                let s = expr_to_string(&args.next()?)?;
+                out.push_str(&pad(s, *mincol, true));
+            }
+            Directive::Fixed { width, digits } => {
+                let n = expect_number(&args.next()?)?.to_f64();
+                let s = match digits {
+                    Some(digits) => format!("{:.*}", digits, n),
+                    None => LispNumber::Float(n).to_string(),
+                };
+                out.push_str(&pad(s, *width, true));
+            }
+            Directive::Newline(count) => out.push_str(&"\n".repeat(*count)),
+            Directive::Iterate(body) => {
+                let mut items = Args {
+                    items: expect_list(&args.next()?)?.into_iter(),
+                };
+                while items.remaining() > 0 {
+                    let before = items.remaining();
+                    // Stop on a body that takes no arguments rather than
+                    // looping forever
+                    if run(body, &mut items, out)? || items.remaining() == before {
+                        break;
+                    }
+                }
+            }
+            Directive::Select { clauses, boolean } => {
+                let arg = args.next()?;
+                let index = if *boolean {
+                    Some(arg.is_truthy() as usize)
+                } else {
+                    match arg {
+                        LispExpr::Number(LispNumber::Integer(n)) => usize::try_from(n).ok(),
+                        LispExpr::Number(LispNumber::Big(_)) => None,
+                        expr => return Err(expr.mismatch(LispTypeId::Integer)),
+                    }
+                };
+                // An index past the last clause selects nothing
+                if let Some(clause) = index.and_then(|i| clauses.get(i))
+                    && run(clause, args, out)?
+                {
+                    return Ok(true);
+                }
+            }
+            Directive::Escape => {
+                if args.remaining() == 0 {
+                    return Ok(true);
+                }
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// `(format dest control args...)` formats the arguments as directed by the
+/// `control` string. With a nil `dest` the result is returned as a string,
+/// and otherwise, as with `t`, it is written to stdout.
+pub fn lisp_format(eval: &Evaluator, mut args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let mut parser = Parser {
+        chars: expect_string(&args[1])?.chars().peekable(),
+    };
+    let directives = match parser.parse()? {
+        (directives, None) => directives,
+        (_, Some(_)) => return Err(EvalError::BadFormat("unmatched closing directive")),
+    };
+
+    let dest = args[0].clone();
+    let mut args = Args {
+        items: args.split_off(2).into_iter(),
+    };
+    let mut out = String::new();
+    run(&directives, &mut args, &mut out)?;
+
+    match dest {
+        LispExpr::Null => Ok(LispExpr::String(out.into())),
+        _ => {
+            print!("{}", out);
+            Ok(LispExpr::Null)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(n: i64) -> LispExpr {
+        LispExpr::Number(LispNumber::Integer(n))
+    }
+
+    fn float(n: f64) -> LispExpr {
+        LispExpr::Number(LispNumber::Float(n))
+    }
+
+    fn list_of(n: i64) -> LispExpr {
+        LispExpr::list((0..n).map(int).collect())
+    }
+
+    fn string(s: &str) -> LispExpr {
+        LispExpr::String(s.into())
+    }
+
+    /// Formats `args` with `control` into a string, or the printed error.
+    fn format(control: &str, args: Vec<LispExpr>) -> Result<String, String> {
+        let mut all = vec![LispExpr::Null, string(control)];
+        all.extend(args);
+        match lisp_format(&Evaluator::new(), all) {
+            Ok(LispExpr::String(s)) => Ok(s.to_string()),
+            Ok(expr) => panic!("format returned {:?}", expr_to_string(&expr)),
+            Err(e) => Err(format!("{:?}", e)),
+        }
+    }
+
+    #[test]
+    fn print_directives_pad_to_mincol() {
+        assert_eq!(
+            format("[~10a]", vec![string("abc")]).unwrap(),
+            "[abc       ]"
+        );
+        assert_eq!(
+            format("[~10@a]", vec![string("abc")]).unwrap(),
+            "[       abc]"
+        );
+        assert_eq!(format("[~2a]", vec![string("abcd")]).unwrap(), "[abcd]");
+        assert_eq!(format("[~8s]", vec![string("ab")]).unwrap(), "[\"ab\"    ]");
+        assert_eq!(format("[~5d]", vec![int(-42)]).unwrap(), "[  -42]");
+    }
+
+    #[test]
+    fn fixed_directive_rounds_to_digits() {
+        assert_eq!(format("~,2f", vec![float(1.23456)]).unwrap(), "1.23");
+        assert_eq!(format("~,2f", vec![int(2)]).unwrap(), "2.00");
+        assert_eq!(format("[~8,3f]", vec![float(-1.5)]).unwrap(), "[  -1.500]");
+        assert_eq!(format("~f", vec![float(0.25)]).unwrap(), "0.25");
+    }
+
+    #[test]
+    fn iteration_stops_at_escape() {
+        assert_eq!(format("~{~a~^, ~}", vec![list_of(3)]).unwrap(), "0, 1, 2");
+        assert_eq!(format("~{~a~^, ~}", vec![LispExpr::Null]).unwrap(), "");
+        assert_eq!(format("~{x~}", vec![list_of(3)]).unwrap(), "x");
+        assert_eq!(format("~a~^ and ~a", vec![int(1)]).unwrap(), "1");
+    }
+
+    #[test]
+    fn select_picks_a_clause() {
+        let control = "~[zero~;one~;two~]";
+        assert_eq!(format(control, vec![int(1)]).unwrap(), "one");
+        assert_eq!(format(control, vec![int(3)]).unwrap(), "");
+        assert_eq!(format(control, vec![int(-1)]).unwrap(), "");
+        assert_eq!(
+            format(control, vec![string("1")]),
+            Err("TypeMismatch { expected: Integer, found: String }".to_string())
+        );
+
+        let control = "~:[no~;yes~]";
+        assert_eq!(format(control, vec![LispExpr::Null]).unwrap(), "no");
+        assert_eq!(format(control, vec![int(0)]).unwrap(), "yes");
+    }
+
+    #[test]
+    fn malformed_control_strings_are_rejected() {
+        let cases = [
+            ("~{~a", "unclosed ~{"),
+            ("~[a~;b", "unclosed ~["),
+            ("a~}", "unmatched closing directive"),
+            ("~]", "unmatched closing directive"),
+            ("~:[a~]", "~:[ needs two clauses"),
+            ("~q", "unknown directive"),
+            ("~", "unfinished directive"),
+            ("~a ~a", "not enough arguments"),
+        ];
+        for (control, message) in cases {
+            assert_eq!(
+                format(control, vec![int(1)]),
+                Err(format!("BadFormat({:?})", message)),
+                "{}",
+                control
+            );
+        }
+    }
+}
@@ -1,133 +1,313 @@
-use std::any::Any;
+use std::rc::Rc;
 
-use crate::{interpreter::EvalError, lisp::internal::*};
+use crate::{
+    bytecode::{chunk::Prototype, vm::Frame},
+    interpreter::{EvalError, Evaluator},
+    lisp::{
+        arith::*,
+        env::Env,
+        format::*,
+        internal::*,
+        list::*,
+        math::*,
+        random::*,
+        seq::*,
+        string::*,
+        symbol::{self, Symbol},
+    },
+    token::escape_symbol,
+};
 
 #[derive(Clone)]
 pub enum LispFunction {
     Internal {
-        name: LispSymbol,
-        args: LispList,
-        func: fn(&Context, Vec<LispExpr>) -> Result<LispExpr, EvalError>,
+        name: Symbol,
+        /// The declared lambda list, which calls are checked against
+        params: Params,
+        func: fn(&Evaluator, Vec<LispExpr>) -> Result<LispExpr, EvalError>,
     },
+    /// A closure created by `lambda` or `define`
     Lisp {
-        name: LispSymbol,
-        args: LispList,
-        body: LispList,
+        name: Symbol,
+        args: LispExpr,
+        body: LispExpr,
+        /// The names the body defines, which are local to each call
+        defines: Vec<Symbol>,
+        env: Rc<Env>,
+    },
+    /// A closure over a VM frame, created by running compiled code
+    Compiled {
+        proto: Rc<Prototype>,
+        frame: Rc<Frame>,
     },
 }
 
-impl LispType for LispFunction {
-    fn value(&self) -> Box<dyn Any> {
-        Box::new(self.clone())
+/// The values as `print` and `println` write them, separated by spaces.
+fn print_values(args: &[LispExpr]) -> Result<String, EvalError> {
+    let parts = args
+        .iter()
+        .map(expr_to_string)
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(parts.join(" "))
+}
+
+/// A lambda list, `(required... &optional optional... &rest rest &key
+/// keys...)`, where `&optional`, `&rest` and `&key` may each be left out.
+#[derive(Clone)]
+pub(crate) struct Params {
+    pub required: Vec<Symbol>,
+    pub optional: Vec<Symbol>,
+    pub rest: Option<Symbol>,
+    pub keys: Vec<Symbol>,
+}
+
+impl Params {
+    /// Splits a lambda list, or returns `None` if it isn't in that shape.
+    pub(crate) fn parse(params: &[Symbol]) -> Option<Params> {
+        let is_marker = |p: Symbol| p == symbol::OPTIONAL || p == symbol::REST || p == symbol::KEY;
+        let mut parsed = Params {
+            required: Vec::new(),
+            optional: Vec::new(),
+            rest: None,
+            keys: Vec::new(),
+        };
+
+        let mut params = params.iter().copied().peekable();
+        while let Some(param) = params.next_if(|p| !is_marker(*p)) {
+            parsed.required.push(param);
+        }
+        if params.next_if_eq(&symbol::OPTIONAL).is_some() {
+            while let Some(param) = params.next_if(|p| !is_marker(*p)) {
+                parsed.optional.push(param);
+            }
+        }
+        if params.next_if_eq(&symbol::REST).is_some() {
+            parsed.rest = Some(params.next().filter(|p| !is_marker(*p))?);
+        }
+        if params.next_if_eq(&symbol::KEY).is_some() {
+            while let Some(param) = params.next_if(|p| !is_marker(*p)) {
+                parsed.keys.push(param);
+            }
+        }
+
+        params.next().is_none().then_some(parsed)
     }
 
-    fn ltype(&self) -> LispTypeId {
-        LispTypeId::Function
+    /// Every parameter name, in the order `bind_args` gives their values.
+    pub(crate) fn names(&self) -> impl Iterator<Item = Symbol> + '_ {
+        self.required
+            .iter()
+            .chain(&self.optional)
+            .chain(&self.rest)
+            .chain(&self.keys)
+            .copied()
     }
-}
 
-pub fn lisp_println(context: &Context, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
-    if args.len() != 1 {
-        return Err(EvalError::AAAA);
+    /// Checks that `found` arguments fit the lambda list.
+    pub(crate) fn check_arity(&self, found: usize) -> Result<(), EvalError> {
+        check_arity(
+            self.required.len(),
+            self.optional.len(),
+            self.rest.is_some() || !self.keys.is_empty(),
+            found,
+        )
     }
+}
+
+/// Checks that `found` arguments are enough for `required` parameters and,
+/// unless `variadic`, no more than those and `optional` ones.
+fn check_arity(
+    required: usize,
+    optional: usize,
+    variadic: bool,
+    found: usize,
+) -> Result<(), EvalError> {
+    let expected = if found < required {
+        required
+    } else if !variadic && found > required + optional {
+        required + optional
+    } else {
+        return Ok(());
+    };
+    Err(EvalError::ArityMismatch { expected, found })
+}
 
-    let expr = args[0].clone();
+/// Matches evaluated arguments to `required` parameters, then `optional`
+/// ones, which default to nil, then a `&rest` list of the arguments after
+/// them if `rest`, then each of `keys`, which take their values from
+/// `:key value` pairs after the positional arguments and default to nil.
+pub(crate) fn bind_args(
+    required: usize,
+    optional: usize,
+    rest: bool,
+    keys: &[Symbol],
+    mut args: Vec<LispExpr>,
+) -> Result<Vec<LispExpr>, EvalError> {
+    check_arity(required, optional, rest || !keys.is_empty(), args.len())?;
 
-    match expr {
-        LispExpr::Literal(s) => {
-            if s.ltype() != LispTypeId::String {
-                return Err(EvalError::AAAA);
-            }
-            let str = s.value().downcast::<LispString>().unwrap();
-            println!("{}", str.value);
-        }
-        _ => return Err(EvalError::AAAA),
+    let positional = required + optional;
+    let extra = args.split_off(positional.min(args.len()));
+    args.resize(positional, LispExpr::Null);
+    let values = if keys.is_empty() {
+        Vec::new()
+    } else {
+        keyword_args(keys, &extra)?
+    };
+    if rest {
+        args.push(LispExpr::list(extra));
     }
+    args.extend(values);
+    Ok(args)
+}
 
+/// The values of `keys` in a list of `:key value` pairs. Only the first
+/// value given for a key counts.
+fn keyword_args(keys: &[Symbol], args: &[LispExpr]) -> Result<Vec<LispExpr>, EvalError> {
+    let mut values = vec![None; keys.len()];
+    for pair in args.chunks(2) {
+        let bad = || EvalError::BadKeywordArgument(expr_to_string(&pair[0]).unwrap_or_default());
+        let (LispExpr::Keyword(key), [_, value]) = (&pair[0], pair) else {
+            return Err(bad());
+        };
+        let i = keys.iter().position(|k| k == key).ok_or_else(bad)?;
+        values[i].get_or_insert_with(|| value.clone());
+    }
+    Ok(values
+        .into_iter()
+        .map(|value| value.unwrap_or(LispExpr::Null))
+        .collect())
+}
+
+pub fn lisp_print(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    print!("{}", print_values(&args)?);
     Ok(LispExpr::Null)
 }
 
-pub fn lisp_add(context: &Context, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
-    if args.len() < 2 {
-        return Err(EvalError::AAAA);
-    }
+pub fn lisp_println(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    println!("{}", print_values(&args)?);
+    Ok(LispExpr::Null)
+}
 
-    let mut nums: Vec<LispNumber> = Vec::new();
-    for arg in &args {
-        if let LispExpr::Literal(lit) = arg {
-            if lit.ltype() != LispTypeId::Number {
-                return Err(EvalError::AAAA);
-            }
+pub fn lisp_gensym(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    Ok(LispExpr::Symbol(Symbol::gensym()))
+}
 
-            nums.push(*lit.value().downcast::<LispNumber>().unwrap());
-        } else {
-            return Err(EvalError::AAAA);
-        }
+pub fn lisp_symbol_to_string(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    match &args[0] {
+        LispExpr::Symbol(s) | LispExpr::Keyword(s) => Ok(LispExpr::String(s.name())),
+        expr => Err(expr.mismatch(LispTypeId::Symbol)),
     }
+}
 
-    let mut sum = LispNumber { value: 0.0 };
-    for num in nums {
-        sum.value += num.value;
+pub fn lisp_string_to_symbol(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    match &args[0] {
+        LispExpr::String(s) => Ok(LispExpr::Symbol(Symbol::intern(s))),
+        expr => Err(expr.mismatch(LispTypeId::String)),
     }
+}
 
-    Ok(LispExpr::Literal(Box::new(sum)))
+/// `(eq a b)` compares by identity, so symbols and keywords with the same
+/// name are `eq`, but equal lists or strings built separately aren't.
+pub fn lisp_eq(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    Ok(LispExpr::from_bool(args[0].eq(&args[1])))
 }
 
-pub fn lisp_to_string(context: &Context, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
-    if args.len() != 1 {
-        return Err(EvalError::AAAA);
+/// `(equal a b)` compares structurally, e.g. lists by their elements.
+pub fn lisp_equal(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    Ok(LispExpr::from_bool(args[0].equal(&args[1])))
+}
+
+fn symbol_to_string(sym: Symbol) -> String {
+    if sym.is_interned() {
+        escape_symbol(&sym.name())
+    } else {
+        // Uninterned symbols can't be read back, so mark them as such
+        format!("#:{}", sym.name())
     }
+}
 
-    match &args[0] {
-        LispExpr::Literal(lit) => match lit.ltype() {
-            LispTypeId::Symbol => Ok(LispExpr::Literal(Box::new(LispString {
-                value: "'".to_string() + &lit.value().downcast::<LispSymbol>().unwrap().value,
-            }))),
-            LispTypeId::Number => Ok(LispExpr::Literal(Box::new(LispString {
-                value: lit
-                    .value()
-                    .downcast::<LispNumber>()
-                    .unwrap()
-                    .value
-                    .to_string(),
-            }))),
-            LispTypeId::List => {
-                let mut repr = "(".to_string();
-                let list = lit.value().downcast::<LispList>().unwrap().value;
-                for expr in list {
-                    match lisp_to_string(context, vec![expr]) {
-                        Ok(expr) => {
-                            if let LispExpr::Literal(lit) = expr {
-                                let str = lit.value().downcast::<LispString>().unwrap().value;
-                                repr.push_str(str.as_str());
-                            }
-                        }
-                        Err(e) => return Err(e),
-                    }
-                    repr.push_str(", ");
-                }
-                // remove last comma
-                repr.pop();
-                repr.pop();
-                repr.push(')');
-                Ok(LispExpr::Literal(Box::new(LispString { value: repr })))
+/// Printed representation of `expr`, as produced by `string`.
+pub fn expr_to_string(expr: &LispExpr) -> Result<String, EvalError> {
+    print_expr(expr, false)
+}
+
+/// Like `expr_to_string`, but with strings in quotes, as `format`'s `~s`
+/// prints them.
+pub fn expr_to_readable(expr: &LispExpr) -> Result<String, EvalError> {
+    print_expr(expr, true)
+}
+
+/// Prints `expr`, keeping the lists still being printed on a heap stack so
+/// that deeply nested lists don't overflow the host stack.
+fn print_expr(expr: &LispExpr, readable: bool) -> Result<String, EvalError> {
+    let mut out = String::new();
+    // What remains of each list being printed, innermost last
+    let mut tails = Vec::new();
+    let mut next = Some(expr.clone());
+    loop {
+        match next.take() {
+            Some(LispExpr::Pair(pair)) => {
+                out.push('(');
+                tails.push(pair.cdr.clone());
+                next = Some(pair.car.clone());
+                continue;
+            }
+            Some(atom) => out.push_str(&print_atom(&atom, readable)),
+            None => {}
+        }
+
+        let Some(tail) = tails.pop() else {
+            return Ok(out);
+        };
+        match tail {
+            LispExpr::Null => out.push(')'),
+            LispExpr::Pair(pair) => {
+                out.push(' ');
+                tails.push(pair.cdr.clone());
+                next = Some(pair.car.clone());
             }
-            LispTypeId::String => return Ok(LispExpr::Literal(lit.clone())),
-            LispTypeId::Function => {
-                return Err(EvalError::AAAA);
+            tail => {
+                out.push_str(" . ");
+                out.push_str(&print_atom(&tail, readable));
+                out.push(')');
+            }
+        }
+    }
+}
+
+fn print_atom(expr: &LispExpr, readable: bool) -> String {
+    match expr {
+        LispExpr::Symbol(s) => symbol_to_string(*s),
+        LispExpr::Number(n) => n.to_string(),
+        LispExpr::Pair(_) => unreachable!("pairs are printed by print_expr"),
+        LispExpr::Keyword(k) => ":".to_string() + &escape_symbol(&k.name()),
+        LispExpr::String(s) if readable => format!("\"{}\"", s),
+        LispExpr::String(s) => s.to_string(),
+        LispExpr::Function(f) => match f.as_ref() {
+            LispFunction::Internal { name, .. } | LispFunction::Lisp { name, .. } => {
+                format!("#<function {}>", symbol_to_string(*name))
+            }
+            LispFunction::Compiled { proto, .. } => {
+                format!("#<function {}>", symbol_to_string(proto.name))
             }
         },
-        LispExpr::Null => Ok(LispExpr::Literal(Box::new(LispString {
-            value: "nil".to_string(),
-        }))),
+        LispExpr::Null => "nil".to_string(),
+    }
+}
+
+pub fn lisp_to_string(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    match &args[0] {
+        LispExpr::String(_) => Ok(args[0].clone()),
+        expr => Ok(LispExpr::String(expr_to_string(expr)?.into())),
     }
 }
 
 macro_rules! declare_internal {
-    ($func:ident, $name:expr, $($arg:expr),*) => {
+    ($func:ident, $name:expr $(, $arg:expr)*) => {
         LispFunction::Internal {
-            name: LispSymbol { value: $name.to_string() },
-            args: LispList { value: vec![$(LispExpr::Literal(Box::new(LispSymbol { value: $arg.to_string() }))),*] },
+            name: Symbol::intern($name),
+            params: Params::parse(&[$(Symbol::intern($arg)),*])
+                .expect(concat!("bad lambda list for ", $name)),
             func: $func
         }
     };
@@ -135,8 +315,108 @@ macro_rules! declare_internal {
 
 pub fn get_internal_functions() -> Vec<LispFunction> {
     vec![
-        declare_internal!(lisp_println, "println", "str"),
-        declare_internal!(lisp_add, "+", "num1", "num2", "&rest"),
+        declare_internal!(lisp_println, "println", "&rest", "values"),
+        declare_internal!(lisp_print, "print", "&rest", "values"),
+        declare_internal!(lisp_format, "format", "dest", "control", "&rest", "args"),
+        declare_internal!(lisp_add, "+", "&rest", "nums"),
+        declare_internal!(lisp_sub, "-", "num", "&rest", "nums"),
+        declare_internal!(lisp_mul, "*", "&rest", "nums"),
         declare_internal!(lisp_to_string, "string", "param"),
+        declare_internal!(lisp_div, "/", "num", "&rest", "nums"),
+        declare_internal!(lisp_mod, "mod", "num", "divisor"),
+        declare_internal!(lisp_rem, "rem", "num", "divisor"),
+        declare_internal!(lisp_num_eq, "=", "num", "&rest", "nums"),
+        declare_internal!(lisp_lt, "<", "num", "&rest", "nums"),
+        declare_internal!(lisp_le, "<=", "num", "&rest", "nums"),
+        declare_internal!(lisp_gt, ">", "num", "&rest", "nums"),
+        declare_internal!(lisp_ge, ">=", "num", "&rest", "nums"),
+        declare_internal!(lisp_min, "min", "num", "&rest", "nums"),
+        declare_internal!(lisp_max, "max", "num", "&rest", "nums"),
+        declare_internal!(lisp_abs, "abs", "num"),
+        declare_internal!(lisp_sqrt, "sqrt", "num"),
+        declare_internal!(lisp_expt, "expt", "base", "power"),
+        declare_internal!(lisp_exp, "exp", "num"),
+        declare_internal!(lisp_log, "log", "num", "&optional", "base"),
+        declare_internal!(lisp_sin, "sin", "num"),
+        declare_internal!(lisp_cos, "cos", "num"),
+        declare_internal!(lisp_tan, "tan", "num"),
+        declare_internal!(lisp_atan2, "atan2", "y", "x"),
+        declare_internal!(lisp_floor, "floor", "num"),
+        declare_internal!(lisp_ceiling, "ceiling", "num"),
+        declare_internal!(lisp_round, "round", "num"),
+        declare_internal!(lisp_truncate, "truncate", "num"),
+        declare_internal!(lisp_gcd, "gcd", "&rest", "ints"),
+        declare_internal!(lisp_lcm, "lcm", "&rest", "ints"),
+        declare_internal!(lisp_logand, "logand", "&rest", "ints"),
+        declare_internal!(lisp_logior, "logior", "&rest", "ints"),
+        declare_internal!(lisp_logxor, "logxor", "&rest", "ints"),
+        declare_internal!(lisp_ash, "ash", "int", "count"),
+        declare_internal!(lisp_cons, "cons", "car", "cdr"),
+        declare_internal!(lisp_car, "car", "pair"),
+        declare_internal!(lisp_cdr, "cdr", "pair"),
+        declare_internal!(lisp_list, "list", "&rest", "items"),
+        declare_internal!(lisp_length, "length", "list"),
+        declare_internal!(lisp_append, "append", "&rest", "lists"),
+        declare_internal!(lisp_reverse, "reverse", "list"),
+        declare_internal!(lisp_nth, "nth", "index", "list"),
+        declare_internal!(lisp_last, "last", "list"),
+        declare_internal!(lisp_member, "member", "item", "list"),
+        declare_internal!(lisp_assoc, "assoc", "key", "alist"),
+        declare_internal!(lisp_map, "map", "func", "list", "&rest", "lists"),
+        declare_internal!(lisp_for_each, "for-each", "func", "list", "&rest", "lists"),
+        declare_internal!(lisp_filter, "filter", "pred", "list"),
+        declare_internal!(lisp_reduce, "reduce", "func", "init", "list"),
+        declare_internal!(lisp_reduce, "fold", "func", "init", "list"),
+        declare_internal!(lisp_apply, "apply", "func", "arg", "&rest", "args"),
+        declare_internal!(lisp_find, "find", "pred", "list"),
+        declare_internal!(lisp_every, "every", "pred", "list", "&rest", "lists"),
+        declare_internal!(lisp_some, "some", "pred", "list", "&rest", "lists"),
+        declare_internal!(lisp_sort, "sort", "list", "less"),
+        declare_internal!(lisp_random, "random", "limit"),
+        declare_internal!(lisp_random_float, "random-float"),
+        declare_internal!(lisp_shuffle, "shuffle", "list"),
+        declare_internal!(lisp_set_random_seed, "set-random-seed!", "seed"),
+        declare_internal!(lisp_numerator, "numerator", "num"),
+        declare_internal!(lisp_denominator, "denominator", "num"),
+        declare_internal!(lisp_exact_to_inexact, "exact->inexact", "num"),
+        declare_internal!(lisp_rationalize, "rationalize", "num", "tolerance"),
+        declare_internal!(
+            lisp_number_to_string,
+            "number->string",
+            "num",
+            "&optional",
+            "radix"
+        ),
+        declare_internal!(lisp_string_append, "string-append", "&rest", "strs"),
+        declare_internal!(lisp_string_length, "string-length", "str"),
+        declare_internal!(
+            lisp_substring,
+            "substring",
+            "str",
+            "start",
+            "&optional",
+            "end"
+        ),
+        declare_internal!(lisp_string_split, "string-split", "str", "&optional", "sep"),
+        declare_internal!(lisp_string_join, "string-join", "strs", "&optional", "sep"),
+        declare_internal!(lisp_string_trim, "string-trim", "str"),
+        declare_internal!(lisp_string_upcase, "string-upcase", "str"),
+        declare_internal!(lisp_string_downcase, "string-downcase", "str"),
+        declare_internal!(lisp_string_index, "string-index", "str", "pattern"),
+        declare_internal!(lisp_string_contains, "string-contains?", "str", "pattern"),
+        declare_internal!(lisp_string_replace, "string-replace", "str", "from", "to"),
+        declare_internal!(lisp_string_to_list, "string->list", "str"),
+        declare_internal!(
+            lisp_string_to_number,
+            "string->number",
+            "str",
+            "&optional",
+            "radix"
+        ),
+        declare_internal!(lisp_gensym, "gensym"),
+        declare_internal!(lisp_symbol_to_string, "symbol->string", "sym"),
+        declare_internal!(lisp_string_to_symbol, "string->symbol", "str"),
+        declare_internal!(lisp_eq, "eq", "a", "b"),
+        declare_internal!(lisp_equal, "equal", "a", "b"),
     ]
 }
@@ -1,124 +1,257 @@
-use std::any::Any;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::rc::Rc;
 
+use crate::interpreter::EvalError;
+use crate::lisp::env::Env;
 use crate::lisp::function::*;
+use crate::lisp::number::LispNumber;
+use crate::lisp::random::Rng;
+use crate::lisp::symbol::{self, Symbol};
 
-pub trait LispType: LispTypeBoxClone {
-    fn value(&self) -> Box<dyn Any>;
-    fn ltype(&self) -> LispTypeId;
-}
-
-#[derive(PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum LispTypeId {
+    Null,
     Number,
+    /// Only ever expected: integers have the type `Number`
+    Integer,
     String,
     List,
     Symbol,
+    Keyword,
     Function,
 }
 
-#[derive(Clone)]
-pub struct LispString {
-    pub value: String,
+/// A cons cell. Lists are chains of pairs ending in `LispExpr::Null`, and
+/// since pairs are immutable and shared, lists built with `cons` share
+/// their tails instead of copying them.
+pub struct LispPair {
+    pub car: LispExpr,
+    pub cdr: LispExpr,
 }
 
-impl LispType for LispString {
-    fn value(&self) -> Box<dyn Any> {
-        Box::new(self.clone())
+impl Drop for LispPair {
+    // Unlink nested pairs iteratively rather than recursing once per pair,
+    // whether they make a long list or a deeply nested one
+    fn drop(&mut self) {
+        let mut pending = Vec::new();
+        self.unlink(&mut pending);
+        while let Some(pair) = pending.pop() {
+            if let Ok(mut pair) = Rc::try_unwrap(pair) {
+                pair.unlink(&mut pending);
+            }
+        }
     }
+}
 
-    fn ltype(&self) -> LispTypeId {
-        LispTypeId::String
+impl LispPair {
+    /// Moves the pairs this one holds onto `pending`.
+    fn unlink(&mut self, pending: &mut Vec<Rc<LispPair>>) {
+        for field in [&mut self.car, &mut self.cdr] {
+            if let LispExpr::Pair(_) = field
+                && let LispExpr::Pair(pair) = std::mem::replace(field, LispExpr::Null)
+            {
+                pending.push(pair);
+            }
+        }
     }
 }
 
-#[derive(Clone)]
-pub struct LispSymbol {
-    pub value: String,
+/// Iterator over the elements of a list. Once exhausted, `tail` holds
+/// whatever ended the list: nil for a proper list, anything else for an
+/// improper one.
+pub struct ListIter {
+    pub tail: LispExpr,
 }
 
-impl LispType for LispSymbol {
-    fn value(&self) -> Box<dyn Any> {
-        Box::new(self.clone())
-    }
+impl Iterator for ListIter {
+    type Item = LispExpr;
 
-    fn ltype(&self) -> LispTypeId {
-        LispTypeId::Symbol
+    fn next(&mut self) -> Option<LispExpr> {
+        let (car, cdr) = match &self.tail {
+            LispExpr::Pair(pair) => (pair.car.clone(), pair.cdr.clone()),
+            _ => return None,
+        };
+        self.tail = cdr;
+        Some(car)
     }
 }
 
+/// A Lisp value. Immediate values are stored inline and heap payloads are
+/// reference counted, so cloning a value never copies a string or list.
 #[derive(Clone)]
-pub struct LispNumber {
-    pub value: f32,
+pub enum LispExpr {
+    Null,
+    Number(LispNumber),
+    String(Rc<str>),
+    Symbol(Symbol),
+    /// A `:name` symbol, which evaluates to itself
+    Keyword(Symbol),
+    Pair(Rc<LispPair>),
+    Function(Rc<LispFunction>),
 }
 
-impl LispType for LispNumber {
-    fn value(&self) -> Box<dyn Any> {
-        Box::new(self.clone())
+impl LispExpr {
+    pub fn ltype(&self) -> LispTypeId {
+        match self {
+            LispExpr::Null => LispTypeId::Null,
+            LispExpr::Number(_) => LispTypeId::Number,
+            LispExpr::String(_) => LispTypeId::String,
+            LispExpr::Symbol(_) => LispTypeId::Symbol,
+            LispExpr::Keyword(_) => LispTypeId::Keyword,
+            LispExpr::Pair(_) => LispTypeId::List,
+            LispExpr::Function(_) => LispTypeId::Function,
+        }
     }
 
-    fn ltype(&self) -> LispTypeId {
-        LispTypeId::Number
+    /// `t` for true and nil for false.
+    pub fn from_bool(b: bool) -> LispExpr {
+        if b {
+            LispExpr::Symbol(symbol::T)
+        } else {
+            LispExpr::Null
+        }
     }
-}
 
-#[derive(Clone)]
-pub struct LispList {
-    pub value: Vec<LispExpr>,
-}
+    /// Identity: the same symbol or keyword, the same integer or float, or
+    /// the very same string, list or function rather than an equal copy.
+    pub fn eq(&self, other: &LispExpr) -> bool {
+        match (self, other) {
+            (LispExpr::Null, LispExpr::Null) => true,
+            (LispExpr::Symbol(a), LispExpr::Symbol(b))
+            | (LispExpr::Keyword(a), LispExpr::Keyword(b)) => a == b,
+            (
+                LispExpr::Number(LispNumber::Integer(a)),
+                LispExpr::Number(LispNumber::Integer(b)),
+            ) => a == b,
+            (LispExpr::Number(LispNumber::Float(a)), LispExpr::Number(LispNumber::Float(b))) => {
+                a.to_bits() == b.to_bits()
+            }
+            (LispExpr::Number(LispNumber::Big(a)), LispExpr::Number(LispNumber::Big(b))) => {
+                Rc::ptr_eq(a, b)
+            }
+            (LispExpr::Number(LispNumber::Ratio(a)), LispExpr::Number(LispNumber::Ratio(b))) => {
+                Rc::ptr_eq(a, b)
+            }
+            (LispExpr::String(a), LispExpr::String(b)) => Rc::ptr_eq(a, b),
+            (LispExpr::Pair(a), LispExpr::Pair(b)) => Rc::ptr_eq(a, b),
+            (LispExpr::Function(a), LispExpr::Function(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
 
-impl LispType for LispList {
-    fn value(&self) -> Box<dyn Any> {
-        Box::new(self.clone())
+    /// Structural equality: numbers of the same exactness and value, strings
+    /// with the same contents, the same symbol, or lists of equal elements.
+    /// Functions are only equal to themselves.
+    pub fn equal(&self, other: &LispExpr) -> bool {
+        match (self, other) {
+            (LispExpr::Null, LispExpr::Null) => true,
+            (LispExpr::Number(a), LispExpr::Number(b)) => {
+                a.is_exact() == b.is_exact() && a.num_cmp(b) == Some(Ordering::Equal)
+            }
+            (LispExpr::String(a), LispExpr::String(b)) => a == b,
+            (LispExpr::Symbol(a), LispExpr::Symbol(b))
+            | (LispExpr::Keyword(a), LispExpr::Keyword(b)) => a == b,
+            (LispExpr::Pair(_), LispExpr::Pair(_)) => {
+                // Compare pairs off a heap stack rather than recursing, so
+                // long or deeply nested lists can't overflow the host stack
+                let mut pending = vec![(self.clone(), other.clone())];
+                while let Some((a, b)) = pending.pop() {
+                    match (a, b) {
+                        (LispExpr::Pair(x), LispExpr::Pair(y)) => {
+                            if !Rc::ptr_eq(&x, &y) {
+                                pending.push((x.cdr.clone(), y.cdr.clone()));
+                                pending.push((x.car.clone(), y.car.clone()));
+                            }
+                        }
+                        (a, b) => {
+                            if !a.equal(&b) {
+                                return false;
+                            }
+                        }
+                    }
+                }
+                true
+            }
+            (LispExpr::Function(a), LispExpr::Function(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
     }
 
-    fn ltype(&self) -> LispTypeId {
-        LispTypeId::List
+    /// Only nil is false; every other value, including `0` and `""`, is true.
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, LispExpr::Null)
     }
-}
 
-pub trait LispTypeBoxClone {
-    fn box_clone(&self) -> Box<dyn LispType>;
-}
+    pub fn cons(car: LispExpr, cdr: LispExpr) -> LispExpr {
+        LispExpr::Pair(Rc::new(LispPair { car, cdr }))
+    }
 
-impl<T> LispTypeBoxClone for T
-where
-    T: 'static + LispType + Clone,
-{
-    fn box_clone(&self) -> Box<dyn LispType> {
-        Box::new(self.clone())
+    /// Builds a list of `items` whose final cdr is `tail`.
+    pub fn list_with_tail(items: Vec<LispExpr>, tail: LispExpr) -> LispExpr {
+        items
+            .into_iter()
+            .rev()
+            .fold(tail, |cdr, car| LispExpr::cons(car, cdr))
     }
-}
 
-impl Clone for Box<dyn LispType> {
-    fn clone(&self) -> Box<dyn LispType> {
-        self.box_clone()
+    pub fn list(items: Vec<LispExpr>) -> LispExpr {
+        LispExpr::list_with_tail(items, LispExpr::Null)
     }
-}
 
-#[derive(Clone)]
-pub enum LispExpr {
-    Literal(Box<dyn LispType>),
-    Null,
-}
+    pub fn iter(&self) -> ListIter {
+        ListIter { tail: self.clone() }
+    }
+
+    /// Collects a proper list into a `Vec` for random access.
+    pub fn list_to_vec(&self) -> Result<Vec<LispExpr>, EvalError> {
+        let mut iter = self.iter();
+        let items = iter.by_ref().collect();
+        match iter.tail {
+            LispExpr::Null => Ok(items),
+            _ => Err(EvalError::ImproperList),
+        }
+    }
 
-pub struct LispVariable {
-    pub name: String,
-    pub value: LispExpr,
+    /// Error for a value that was expected to be of type `expected`.
+    pub fn mismatch(&self, expected: LispTypeId) -> EvalError {
+        EvalError::TypeMismatch {
+            expected,
+            found: self.ltype(),
+        }
+    }
 }
 
 pub struct Context {
-    pub functions: Vec<LispFunction>,
-    pub variables: Vec<LispVariable>,
+    pub globals: Rc<Env>,
+    /// State for `random` and friends, separate for each evaluator
+    pub rng: RefCell<Rng>,
 }
 
 impl Context {
     pub fn default() -> Context {
+        let mut globals = HashMap::from([
+            (symbol::NIL, LispExpr::Null),
+            (symbol::T, LispExpr::Symbol(symbol::T)),
+            (
+                Symbol::intern("pi"),
+                LispExpr::Number(LispNumber::Float(std::f64::consts::PI)),
+            ),
+            (
+                Symbol::intern("e"),
+                LispExpr::Number(LispNumber::Float(std::f64::consts::E)),
+            ),
+        ]);
+        for func in get_internal_functions() {
+            if let LispFunction::Internal { name, .. } = &func {
+                globals.insert(*name, LispExpr::Function(Rc::new(func)));
+            }
+        }
+
         Context {
-            functions: get_internal_functions(),
-            variables: vec![LispVariable {
-                name: "nil".to_string(),
-                value: LispExpr::Null,
-            }],
+            globals: Env::new(globals, None),
+            rng: RefCell::new(Rng::from_entropy()),
         }
     }
 }
@@ -0,0 +1,139 @@
+use crate::{
+    interpreter::{EvalError, Evaluator},
+    lisp::{internal::*, number::LispNumber},
+};
+
+/// The elements of a proper list.
+pub(crate) fn expect_list(expr: &LispExpr) -> Result<Vec<LispExpr>, EvalError> {
+    match expr {
+        LispExpr::Pair(_) | LispExpr::Null => expr.list_to_vec(),
+        expr => Err(expr.mismatch(LispTypeId::List)),
+    }
+}
+
+/// Iterates over a list in place. Once the iterator is exhausted,
+/// `expect_proper` checks how the list ended.
+fn iter_list(expr: &LispExpr) -> Result<ListIter, EvalError> {
+    match expr {
+        LispExpr::Pair(_) | LispExpr::Null => Ok(expr.iter()),
+        expr => Err(expr.mismatch(LispTypeId::List)),
+    }
+}
+
+fn expect_proper(iter: &ListIter) -> Result<(), EvalError> {
+    match iter.tail {
+        LispExpr::Null => Ok(()),
+        _ => Err(EvalError::ImproperList),
+    }
+}
+
+fn expect_pair(expr: &LispExpr) -> Result<&LispPair, EvalError> {
+    match expr {
+        LispExpr::Pair(pair) => Ok(pair),
+        expr => Err(expr.mismatch(LispTypeId::List)),
+    }
+}
+
+/// A list index, which must be a non-negative integer.
+pub(crate) fn expect_index(expr: &LispExpr) -> Result<usize, EvalError> {
+    match expr {
+        LispExpr::Number(LispNumber::Integer(n)) => {
+            usize::try_from(*n).map_err(|_| EvalError::OutOfRange)
+        }
+        LispExpr::Number(LispNumber::Big(_)) => Err(EvalError::OutOfRange),
+        expr => Err(expr.mismatch(LispTypeId::Integer)),
+    }
+}
+
+pub fn lisp_cons(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    Ok(LispExpr::cons(args[0].clone(), args[1].clone()))
+}
+
+pub fn lisp_car(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    Ok(expect_pair(&args[0])?.car.clone())
+}
+
+pub fn lisp_cdr(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    Ok(expect_pair(&args[0])?.cdr.clone())
+}
+
+pub fn lisp_list(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    Ok(LispExpr::list(args))
+}
+
+pub fn lisp_length(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let mut items = iter_list(&args[0])?;
+    let length = items.by_ref().count();
+    expect_proper(&items)?;
+    Ok(LispExpr::Number(LispNumber::Integer(length as i64)))
+}
+
+/// Concatenates lists. The last argument is shared rather than copied, and
+/// may be any value, which becomes the tail of the result.
+pub fn lisp_append(eval: &Evaluator, mut args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let Some(tail) = args.pop() else {
+        return Ok(LispExpr::Null);
+    };
+
+    let mut items = Vec::new();
+    for arg in &args {
+        items.extend(expect_list(arg)?);
+    }
+    Ok(LispExpr::list_with_tail(items, tail))
+}
+
+pub fn lisp_reverse(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let items = expect_list(&args[0])?;
+    Ok(items
+        .into_iter()
+        .fold(LispExpr::Null, |tail, item| LispExpr::cons(item, tail)))
+}
+
+/// `(nth n list)` is the element at zero-based index `n`. Only the first
+/// `n + 1` elements are walked, so a dotted tail past them goes unchecked.
+pub fn lisp_nth(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let index = expect_index(&args[0])?;
+    let mut items = iter_list(&args[1])?;
+    match items.nth(index) {
+        Some(item) => Ok(item),
+        None => {
+            expect_proper(&items)?;
+            Err(EvalError::OutOfRange)
+        }
+    }
+}
+
+/// The last element of a non-empty list.
+pub fn lisp_last(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let mut items = iter_list(&args[0])?;
+    let last = items.by_ref().last();
+    expect_proper(&items)?;
+    last.ok_or(EvalError::OutOfRange)
+}
+
+/// `(member x list)` is the tail of `list` starting at the first element
+/// `equal` to `x`, or nil.
+pub fn lisp_member(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let mut tail = args[1].clone();
+    loop {
+        let next = match &tail {
+            LispExpr::Pair(pair) if pair.car.equal(&args[0]) => return Ok(tail),
+            LispExpr::Pair(pair) => pair.cdr.clone(),
+            LispExpr::Null => return Ok(LispExpr::Null),
+            _ if matches!(args[1], LispExpr::Pair(_)) => return Err(EvalError::ImproperList),
+            expr => return Err(expr.mismatch(LispTypeId::List)),
+        };
+        tail = next;
+    }
+}
+
+/// `(assoc key alist)` is the first pair in `alist` whose car is `equal` to
+/// `key`, or nil.
+pub fn lisp_assoc(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    for entry in expect_list(&args[1])? {
+        if expect_pair(&entry)?.car.equal(&args[0]) {
+            return Ok(entry);
+        }
+    }
+    Ok(LispExpr::Null)
+}
@@ -0,0 +1,216 @@
+use crate::{
+    interpreter::{EvalError, Evaluator},
+    lisp::{arith::expect_number, bigint::BigInt, internal::*, number::LispNumber, ratio::Ratio},
+};
+
+pub(crate) fn expect_integer(expr: &LispExpr) -> Result<&LispNumber, EvalError> {
+    match expr {
+        LispExpr::Number(n @ (LispNumber::Integer(_) | LispNumber::Big(_))) => Ok(n),
+        expr => Err(expr.mismatch(LispTypeId::Integer)),
+    }
+}
+
+/// Applies a float function to the only argument.
+fn float_op(args: &[LispExpr], op: fn(f64) -> f64) -> Result<LispExpr, EvalError> {
+    let n = expect_number(&args[0])?.to_f64();
+    Ok(LispExpr::Number(LispNumber::Float(op(n))))
+}
+
+/// Rounds the only argument to an integer: exactly with `exact` for a ratio,
+/// or with `inexact` for a float, which stays a float.
+fn round_op(
+    args: &[LispExpr],
+    exact: fn(&Ratio) -> BigInt,
+    inexact: fn(f64) -> f64,
+) -> Result<LispExpr, EvalError> {
+    Ok(LispExpr::Number(match expect_number(&args[0])? {
+        LispNumber::Float(n) => LispNumber::Float(inexact(*n)),
+        LispNumber::Ratio(n) => LispNumber::from_big(exact(n)),
+        n => n.clone(),
+    }))
+}
+
+/// The exact square root of `n`, if it is a perfect square.
+fn exact_sqrt(n: &BigInt) -> Option<BigInt> {
+    let root = n.isqrt();
+    (root.mul(&root) == *n).then_some(root)
+}
+
+/// Exact for exact perfect squares, and a float otherwise.
+pub fn lisp_sqrt(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let n = expect_number(&args[0])?;
+    if n.is_exact()
+        && !n.is_negative()
+        && let Some(ratio) = n.to_ratio()
+        && let (Some(numer), Some(denom)) = (exact_sqrt(ratio.numer()), exact_sqrt(ratio.denom()))
+    {
+        return Ok(LispExpr::Number(LispNumber::from_ratio(Ratio::new(
+            numer, denom,
+        ))));
+    }
+    Ok(LispExpr::Number(LispNumber::Float(n.to_f64().sqrt())))
+}
+
+/// Exact for an exact base and integer power, and a float otherwise.
+pub fn lisp_expt(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let base = expect_number(&args[0])?;
+    let power = expect_number(&args[1])?;
+    let (true, LispNumber::Integer(power)) = (base.is_exact(), power) else {
+        return Ok(LispExpr::Number(LispNumber::Float(
+            base.to_f64().powf(power.to_f64()),
+        )));
+    };
+
+    // Exponentiation by squaring
+    let mut result = LispNumber::Integer(1);
+    let mut square = base.clone();
+    let mut bits = power.unsigned_abs();
+    while bits > 0 {
+        if bits & 1 == 1 {
+            result = result.mul(&square);
+        }
+        bits >>= 1;
+        if bits > 0 {
+            square = square.mul(&square);
+        }
+    }
+
+    if *power < 0 {
+        result = LispNumber::Integer(1).div(&result)?;
+    }
+    Ok(LispExpr::Number(result))
+}
+
+pub fn lisp_exp(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    float_op(&args, f64::exp)
+}
+
+/// `(log x)` is the natural logarithm, `(log x base)` the logarithm in `base`.
+pub fn lisp_log(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    if args.len() == 1 {
+        return float_op(&args, f64::ln);
+    }
+
+    let n = expect_number(&args[0])?.to_f64();
+    let base = expect_number(&args[1])?.to_f64();
+    Ok(LispExpr::Number(LispNumber::Float(n.ln() / base.ln())))
+}
+
+pub fn lisp_sin(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    float_op(&args, f64::sin)
+}
+
+pub fn lisp_cos(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    float_op(&args, f64::cos)
+}
+
+pub fn lisp_tan(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    float_op(&args, f64::tan)
+}
+
+pub fn lisp_atan2(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let y = expect_number(&args[0])?.to_f64();
+    let x = expect_number(&args[1])?.to_f64();
+    Ok(LispExpr::Number(LispNumber::Float(y.atan2(x))))
+}
+
+pub fn lisp_floor(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    round_op(&args, Ratio::floor, f64::floor)
+}
+
+pub fn lisp_ceiling(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    round_op(&args, Ratio::ceil, f64::ceil)
+}
+
+/// Rounds to the nearest integer, and halves to even.
+pub fn lisp_round(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    round_op(&args, Ratio::round, f64::round_ties_even)
+}
+
+pub fn lisp_truncate(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    round_op(&args, Ratio::trunc, f64::trunc)
+}
+
+pub fn lisp_gcd(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let mut acc = BigInt::from_i64(0);
+    for arg in &args {
+        acc = acc.gcd(&expect_integer(arg)?.to_big().unwrap());
+    }
+    Ok(LispExpr::Number(LispNumber::from_big(acc)))
+}
+
+pub fn lisp_lcm(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let mut acc = BigInt::from_i64(1);
+    for arg in &args {
+        let n = expect_integer(arg)?.to_big().unwrap();
+        if n.is_zero() {
+            acc = n;
+        } else if !acc.is_zero() {
+            acc = acc.mul(&n).abs().div_rem(&acc.gcd(&n)).0;
+        }
+    }
+    Ok(LispExpr::Number(LispNumber::from_big(acc)))
+}
+
+/// Folds a bitwise operation over integers in two's complement, with `small`
+/// for `i64`s and `big` on each limb of bignums.
+fn bitwise_op(
+    args: &[LispExpr],
+    init: i64,
+    small: fn(i64, i64) -> i64,
+    big: fn(u32, u32) -> u32,
+) -> Result<LispExpr, EvalError> {
+    let mut acc = LispNumber::Integer(init);
+    for arg in args {
+        acc = match (&acc, expect_integer(arg)?) {
+            (LispNumber::Integer(a), LispNumber::Integer(b)) => LispNumber::Integer(small(*a, *b)),
+            (a, b) => LispNumber::from_big(a.to_big().unwrap().bitwise(&b.to_big().unwrap(), big)),
+        };
+    }
+    Ok(LispExpr::Number(acc))
+}
+
+pub fn lisp_logand(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    bitwise_op(&args, -1, |a, b| a & b, |a, b| a & b)
+}
+
+pub fn lisp_logior(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    bitwise_op(&args, 0, |a, b| a | b, |a, b| a | b)
+}
+
+pub fn lisp_logxor(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    bitwise_op(&args, 0, |a, b| a ^ b, |a, b| a ^ b)
+}
+
+/// `(ash n count)` shifts `n` left by `count` bits, or right, rounding
+/// down, if `count` is negative.
+pub fn lisp_ash(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let n = expect_integer(&args[0])?;
+    let LispNumber::Integer(count) = expect_integer(&args[1])? else {
+        return Err(EvalError::OutOfRange);
+    };
+    if let LispNumber::Integer(0) = n {
+        return Ok(args[0].clone());
+    }
+
+    let shifted = if *count >= 0 {
+        if let LispNumber::Integer(n) = n
+            && *count < 63
+            && (n << count) >> count == *n
+        {
+            return Ok(LispExpr::Number(LispNumber::Integer(n << count)));
+        }
+        let count = u32::try_from(*count).map_err(|_| EvalError::OutOfRange)?;
+        n.to_big().unwrap().mul(&BigInt::pow2(count))
+    } else {
+        let big = n.to_big().unwrap();
+        let count = count.unsigned_abs();
+        if count > big.bits() as u64 {
+            // Every bit is shifted out, leaving only the sign
+            BigInt::from_i64(if big.is_negative() { -1 } else { 0 })
+        } else {
+            Ratio::new(big, BigInt::pow2(count as u32)).floor()
+        }
+    };
+    Ok(LispExpr::Number(LispNumber::from_big(shifted)))
+}
@@ -1,2 +1,14 @@
+pub mod arith;
+pub mod bigint;
+pub mod env;
+pub mod format;
 pub mod function;
 pub mod internal;
+pub mod list;
+pub mod math;
+pub mod number;
+pub mod random;
+pub mod ratio;
+pub mod seq;
+pub mod string;
+pub mod symbol;
@@ -0,0 +1,238 @@
+use std::cmp::Ordering;
+use std::fmt::Display;
+use std::rc::Rc;
+
+use crate::interpreter::EvalError;
+use crate::lisp::{bigint::BigInt, ratio::Ratio};
+
+/// A Lisp number. Integers are exact: they are stored inline while they fit
+/// in an `i64` and promote to a `BigInt` when they don't. Dividing integers
+/// gives an exact `Ratio`. As soon as a float is involved the result is a
+/// float.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LispNumber {
+    Integer(i64),
+    /// An integer outside the range of `i64`
+    Big(Rc<BigInt>),
+    /// A fraction that isn't an integer
+    Ratio(Rc<Ratio>),
+    Float(f64),
+}
+
+impl LispNumber {
+    /// Wraps `n`, demoting it to `Integer` if it fits.
+    pub fn from_big(n: BigInt) -> LispNumber {
+        match n.to_i64() {
+            Some(n) => LispNumber::Integer(n),
+            None => LispNumber::Big(Rc::new(n)),
+        }
+    }
+
+    /// Wraps `n`, demoting it to an integer if its denominator is one.
+    pub fn from_ratio(n: Ratio) -> LispNumber {
+        if n.is_integer() {
+            LispNumber::from_big(n.numer().clone())
+        } else {
+            LispNumber::Ratio(Rc::new(n))
+        }
+    }
+
+    pub fn is_exact(&self) -> bool {
+        !matches!(self, LispNumber::Float(_))
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            LispNumber::Integer(n) => *n as f64,
+            LispNumber::Big(n) => n.to_f64(),
+            LispNumber::Ratio(n) => n.to_f64(),
+            LispNumber::Float(n) => *n,
+        }
+    }
+
+    /// The value as a `BigInt`, if it is an integer.
+    pub fn to_big(&self) -> Option<BigInt> {
+        match self {
+            LispNumber::Integer(n) => Some(BigInt::from_i64(*n)),
+            LispNumber::Big(n) => Some(n.as_ref().clone()),
+            LispNumber::Ratio(_) | LispNumber::Float(_) => None,
+        }
+    }
+
+    /// The exact value as a `Ratio`, if it is finite.
+    pub fn to_ratio(&self) -> Option<Ratio> {
+        match self {
+            LispNumber::Ratio(n) => Some(n.as_ref().clone()),
+            LispNumber::Float(n) => Ratio::from_f64(*n),
+            n => Some(Ratio::from_integer(n.to_big()?)),
+        }
+    }
+
+    /// Applies `exact` to two `i64`s, falling back to `big` if either operand
+    /// is a bignum or the result overflows, to `ratio` if either is a ratio,
+    /// or to `inexact` on both operands as floats if either is one.
+    fn combine(
+        &self,
+        other: &LispNumber,
+        exact: fn(i64, i64) -> Option<i64>,
+        big: fn(&BigInt, &BigInt) -> BigInt,
+        ratio: fn(&Ratio, &Ratio) -> Ratio,
+        inexact: fn(f64, f64) -> f64,
+    ) -> LispNumber {
+        if let (LispNumber::Integer(a), LispNumber::Integer(b)) = (self, other)
+            && let Some(n) = exact(*a, *b)
+        {
+            return LispNumber::Integer(n);
+        }
+
+        match (self, other) {
+            (LispNumber::Float(_), _) | (_, LispNumber::Float(_)) => {
+                LispNumber::Float(inexact(self.to_f64(), other.to_f64()))
+            }
+            (LispNumber::Ratio(_), _) | (_, LispNumber::Ratio(_)) => {
+                LispNumber::from_ratio(ratio(&self.to_ratio().unwrap(), &other.to_ratio().unwrap()))
+            }
+            _ => LispNumber::from_big(big(&self.to_big().unwrap(), &other.to_big().unwrap())),
+        }
+    }
+
+    pub fn add(&self, other: &LispNumber) -> LispNumber {
+        self.combine(other, i64::checked_add, BigInt::add, Ratio::add, |a, b| {
+            a + b
+        })
+    }
+
+    pub fn sub(&self, other: &LispNumber) -> LispNumber {
+        self.combine(other, i64::checked_sub, BigInt::sub, Ratio::sub, |a, b| {
+            a - b
+        })
+    }
+
+    pub fn mul(&self, other: &LispNumber) -> LispNumber {
+        self.combine(other, i64::checked_mul, BigInt::mul, Ratio::mul, |a, b| {
+            a * b
+        })
+    }
+
+    /// Exact division of exact numbers, or float division if either operand
+    /// is a float. Dividing by exact zero fails either way.
+    pub fn div(&self, other: &LispNumber) -> Result<LispNumber, EvalError> {
+        match (self, other) {
+            (_, LispNumber::Integer(0)) => Err(EvalError::DivisionByZero),
+            (LispNumber::Float(_), _) | (_, LispNumber::Float(_)) => {
+                Ok(LispNumber::Float(self.to_f64() / other.to_f64()))
+            }
+            (LispNumber::Integer(a), LispNumber::Integer(b)) if a.checked_rem(*b) == Some(0) => {
+                Ok(LispNumber::Integer(a / b))
+            }
+            _ => Ok(LispNumber::from_ratio(
+                self.to_ratio().unwrap().div(&other.to_ratio().unwrap()),
+            )),
+        }
+    }
+
+    /// The remainder of dividing by `other`. With `floored` it takes the
+    /// sign of `other`, as in `mod`, and otherwise the sign of `self`, as in
+    /// `rem`.
+    pub fn remainder(&self, other: &LispNumber, floored: bool) -> Result<LispNumber, EvalError> {
+        if let (LispNumber::Integer(a), LispNumber::Integer(b)) = (self, other)
+            && *b != 0
+            && let Some(r) = a.checked_rem(*b)
+        {
+            let adjust = floored && r != 0 && (r < 0) != (*b < 0);
+            return Ok(LispNumber::Integer(if adjust { r + b } else { r }));
+        }
+
+        match (self, other) {
+            (_, LispNumber::Integer(0)) => Err(EvalError::DivisionByZero),
+            (LispNumber::Float(_), _) | (_, LispNumber::Float(_)) => {
+                let (a, b) = (self.to_f64(), other.to_f64());
+                let r = a % b;
+                let adjust = floored && r != 0.0 && (r < 0.0) != (b < 0.0);
+                Ok(LispNumber::Float(if adjust { r + b } else { r }))
+            }
+            _ => {
+                let (a, b) = (self.to_ratio().unwrap(), other.to_ratio().unwrap());
+                let quotient = a.div(&b);
+                let quotient = if floored {
+                    quotient.floor()
+                } else {
+                    quotient.trunc()
+                };
+                Ok(LispNumber::from_ratio(
+                    a.sub(&b.mul(&Ratio::from_integer(quotient))),
+                ))
+            }
+        }
+    }
+
+    pub fn neg(&self) -> LispNumber {
+        match self {
+            LispNumber::Integer(n) => match n.checked_neg() {
+                Some(n) => LispNumber::Integer(n),
+                None => LispNumber::from_big(BigInt::from_i64(*n).neg()),
+            },
+            LispNumber::Big(n) => LispNumber::from_big(n.neg()),
+            LispNumber::Ratio(n) => LispNumber::Ratio(Rc::new(n.neg())),
+            LispNumber::Float(n) => LispNumber::Float(-n),
+        }
+    }
+
+    pub fn is_negative(&self) -> bool {
+        match self {
+            LispNumber::Integer(n) => *n < 0,
+            LispNumber::Big(n) => n.is_negative(),
+            LispNumber::Ratio(n) => n.numer().is_negative(),
+            LispNumber::Float(n) => *n < 0.0,
+        }
+    }
+
+    pub fn abs(&self) -> LispNumber {
+        if self.is_negative() {
+            self.neg()
+        } else {
+            self.clone()
+        }
+    }
+
+    /// Numeric ordering, across representations. Only NaN is unordered.
+    pub fn num_cmp(&self, other: &LispNumber) -> Option<Ordering> {
+        match (self, other) {
+            (LispNumber::Integer(a), LispNumber::Integer(b)) => Some(a.cmp(b)),
+            (LispNumber::Float(_), _) | (_, LispNumber::Float(_)) => {
+                self.to_f64().partial_cmp(&other.to_f64())
+            }
+            _ => Some(self.to_ratio()?.cmp(&other.to_ratio()?)),
+        }
+    }
+
+    /// Formats an exact number in `radix`, between 2 and 36. Floats can only
+    /// be formatted in base 10.
+    pub fn to_str_radix(&self, radix: u32) -> Option<String> {
+        match self {
+            LispNumber::Float(_) if radix == 10 => Some(self.to_string()),
+            LispNumber::Float(_) => None,
+            LispNumber::Ratio(n) => Some(format!(
+                "{}/{}",
+                n.numer().to_str_radix(radix),
+                n.denom().to_str_radix(radix)
+            )),
+            n => Some(n.to_big()?.to_str_radix(radix)),
+        }
+    }
+}
+
+impl Display for LispNumber {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LispNumber::Integer(n) => write!(f, "{}", n),
+            LispNumber::Big(n) => write!(f, "{}", n),
+            LispNumber::Ratio(n) => write!(f, "{}", n),
+            // Keep a decimal point so floats read back as floats
+            LispNumber::Float(n) if n.is_finite() && n.fract() == 0.0 => {
+                write!(f, "{:.1}", n)
+            }
+            LispNumber::Float(n) => write!(f, "{}", n),
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+use crate::{
+    interpreter::{EvalError, Evaluator},
+    lisp::{
+        arith::expect_number, bigint::BigInt, internal::*, list::expect_list, math::expect_integer,
+        number::LispNumber,
+    },
+};
+
+/// A xoshiro256** generator. Not suitable for cryptography, but fast, and
+/// reproducible across platforms for a given seed.
+pub struct Rng {
+    state: [u64; 4],
+}
+
+impl Rng {
+    pub fn seeded(seed: u64) -> Rng {
+        // Spread the seed over the whole state with splitmix64, since
+        // xoshiro misbehaves on mostly zero states
+        let mut x = seed;
+        let mut next = || {
+            x = x.wrapping_add(0x9e3779b97f4a7c15);
+            let mut z = x;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+            z ^ (z >> 31)
+        };
+        Rng {
+            state: [next(), next(), next(), next()],
+        }
+    }
+
+    /// A generator seeded from the per-process randomness std uses for
+    /// `HashMap`s.
+    pub fn from_entropy() -> Rng {
+        Rng::seeded(RandomState::new().build_hasher().finish())
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let s = &mut self.state;
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        result
+    }
+
+    /// Uniform in `[0, n)`, for `n` above zero.
+    pub fn below(&mut self, n: u64) -> u64 {
+        // Reject the few values that would make low results more likely
+        let threshold = n.wrapping_neg() % n;
+        loop {
+            let m = self.next_u64() as u128 * n as u128;
+            if m as u64 >= threshold {
+                return (m >> 64) as u64;
+            }
+        }
+    }
+
+    /// Uniform in `[0, n)`, for `n` above zero.
+    pub fn below_big(&mut self, n: &BigInt) -> BigInt {
+        let bits = n.bits();
+        let len = bits.div_ceil(32) as usize;
+        let mask = u32::MAX >> (len as u32 * 32 - bits);
+        loop {
+            let mut limbs: Vec<u32> = (0..len).map(|_| self.next_u64() as u32).collect();
+            limbs[len - 1] &= mask;
+            let candidate = BigInt::from_limbs(limbs);
+            if candidate < *n {
+                return candidate;
+            }
+        }
+    }
+
+    /// Uniform in `[0, 1)`
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// `(random n)` is a random integer below a positive integer `n`, or a
+/// random float below a positive float `n`.
+pub fn lisp_random(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let n = expect_number(&args[0])?;
+    if n.is_negative() || matches!(n, LispNumber::Integer(0)) {
+        return Err(EvalError::OutOfRange);
+    }
+
+    let mut rng = eval.context().rng.borrow_mut();
+    Ok(LispExpr::Number(match n {
+        LispNumber::Integer(n) => LispNumber::Integer(rng.below(*n as u64) as i64),
+        LispNumber::Big(n) => LispNumber::from_big(rng.below_big(n)),
+        LispNumber::Float(n) if *n > 0.0 => LispNumber::Float(rng.next_f64() * n),
+        _ => return Err(EvalError::OutOfRange),
+    }))
+}
+
+pub fn lisp_random_float(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let n = eval.context().rng.borrow_mut().next_f64();
+    Ok(LispExpr::Number(LispNumber::Float(n)))
+}
+
+/// Returns a new list with the elements of a list in random order.
+pub fn lisp_shuffle(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let mut items = expect_list(&args[0])?;
+
+    // Fisher-Yates
+    let mut rng = eval.context().rng.borrow_mut();
+    for i in (1..items.len()).rev() {
+        let j = rng.below(i as u64 + 1) as usize;
+        items.swap(i, j);
+    }
+    Ok(LispExpr::list(items))
+}
+
+pub fn lisp_set_random_seed(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let LispNumber::Integer(seed) = expect_integer(&args[0])? else {
+        return Err(EvalError::OutOfRange);
+    };
+    *eval.context().rng.borrow_mut() = Rng::seeded(*seed as u64);
+    Ok(LispExpr::Null)
+}
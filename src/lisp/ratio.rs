@@ -0,0 +1,271 @@
+use std::cmp::Ordering;
+use std::fmt::Display;
+
+use crate::lisp::bigint::BigInt;
+
+/// An exact fraction in lowest terms with a positive denominator. Integers
+/// are ratios with a denominator of one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Ratio {
+    numer: BigInt,
+    denom: BigInt,
+}
+
+impl Ratio {
+    /// Builds `numer / denom` in lowest terms. Panics if `denom` is zero.
+    pub fn new(numer: BigInt, denom: BigInt) -> Ratio {
+        assert!(!denom.is_zero(), "Ratio with a zero denominator");
+        let divisor = numer.gcd(&denom);
+        let (mut numer, _) = numer.div_rem(&divisor);
+        let (mut denom, _) = denom.div_rem(&divisor);
+        if denom.is_negative() {
+            numer = numer.neg();
+            denom = denom.neg();
+        }
+        Ratio { numer, denom }
+    }
+
+    pub fn from_integer(n: BigInt) -> Ratio {
+        Ratio {
+            numer: n,
+            denom: BigInt::from_i64(1),
+        }
+    }
+
+    /// The exact value of a finite float.
+    pub fn from_f64(n: f64) -> Option<Ratio> {
+        if !n.is_finite() {
+            return None;
+        }
+
+        let bits = n.to_bits();
+        let exponent = ((bits >> 52) & 0x7ff) as i32;
+        let fraction = (bits & ((1 << 52) - 1)) as i64;
+        // Subnormals have no implicit leading bit and a fixed exponent
+        let (mantissa, exponent) = match exponent {
+            0 => (fraction, -1074),
+            _ => (fraction | 1 << 52, exponent - 1075),
+        };
+        let mantissa = BigInt::from_i64(if n < 0.0 { -mantissa } else { mantissa });
+
+        let scale = BigInt::pow2(exponent.unsigned_abs());
+        Some(if exponent >= 0 {
+            Ratio::from_integer(mantissa.mul(&scale))
+        } else {
+            Ratio::new(mantissa, scale)
+        })
+    }
+
+    pub fn numer(&self) -> &BigInt {
+        &self.numer
+    }
+
+    pub fn denom(&self) -> &BigInt {
+        &self.denom
+    }
+
+    pub fn is_integer(&self) -> bool {
+        self.denom == BigInt::from_i64(1)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.numer.is_zero()
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.numer.to_f64() / self.denom.to_f64()
+    }
+
+    pub fn neg(&self) -> Ratio {
+        Ratio {
+            numer: self.numer.neg(),
+            denom: self.denom.clone(),
+        }
+    }
+
+    pub fn add(&self, other: &Ratio) -> Ratio {
+        Ratio::new(
+            self.numer
+                .mul(&other.denom)
+                .add(&other.numer.mul(&self.denom)),
+            self.denom.mul(&other.denom),
+        )
+    }
+
+    pub fn sub(&self, other: &Ratio) -> Ratio {
+        self.add(&other.neg())
+    }
+
+    pub fn mul(&self, other: &Ratio) -> Ratio {
+        Ratio::new(self.numer.mul(&other.numer), self.denom.mul(&other.denom))
+    }
+
+    /// Panics if `other` is zero.
+    pub fn div(&self, other: &Ratio) -> Ratio {
+        Ratio::new(self.numer.mul(&other.denom), self.denom.mul(&other.numer))
+    }
+
+    /// The integer part of the ratio, rounding towards zero.
+    pub fn trunc(&self) -> BigInt {
+        self.numer.div_rem(&self.denom).0
+    }
+
+    /// The smallest integer not less than the ratio.
+    pub fn ceil(&self) -> BigInt {
+        self.neg().floor().neg()
+    }
+
+    /// The nearest integer, rounding halves to even.
+    pub fn round(&self) -> BigInt {
+        let shifted = self.add(&Ratio::new(BigInt::from_i64(1), BigInt::from_i64(2)));
+        let floor = shifted.floor();
+        let odd = !floor.div_rem(&BigInt::from_i64(2)).1.is_zero();
+        if shifted.is_integer() && odd {
+            floor.sub(&BigInt::from_i64(1))
+        } else {
+            floor
+        }
+    }
+
+    /// The largest integer not greater than the ratio.
+    pub fn floor(&self) -> BigInt {
+        let (quotient, rem) = self.numer.div_rem(&self.denom);
+        if rem.is_negative() {
+            quotient.sub(&BigInt::from_i64(1))
+        } else {
+            quotient
+        }
+    }
+
+    /// The ratio with the smallest denominator in the closed interval
+    /// between `lo` and `hi`, found by comparing continued fractions.
+    pub fn simplest_between(lo: &Ratio, hi: &Ratio) -> Ratio {
+        let (lo, hi) = if lo <= hi { (lo, hi) } else { (hi, lo) };
+        if (lo.numer.is_negative() && !hi.numer.is_negative()) || lo.is_zero() {
+            return Ratio::from_integer(BigInt::from_i64(0));
+        }
+        if hi.numer.is_negative() {
+            return Ratio::simplest_between(&hi.neg(), &lo.neg()).neg();
+        }
+
+        let floor = Ratio::from_integer(lo.floor());
+        if floor == *lo {
+            return floor;
+        }
+        let next = floor.add(&Ratio::from_integer(BigInt::from_i64(1)));
+        if next <= *hi {
+            return next;
+        }
+
+        let one = Ratio::from_integer(BigInt::from_i64(1));
+        let inner = Ratio::simplest_between(&one.div(&hi.sub(&floor)), &one.div(&lo.sub(&floor)));
+        floor.add(&one.div(&inner))
+    }
+}
+
+impl Ord for Ratio {
+    fn cmp(&self, other: &Ratio) -> Ordering {
+        self.numer
+            .mul(&other.denom)
+            .cmp(&other.numer.mul(&self.denom))
+    }
+}
+
+impl PartialOrd for Ratio {
+    fn partial_cmp(&self, other: &Ratio) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Display for Ratio {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.numer, self.denom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ratio(numer: i64, denom: i64) -> Ratio {
+        Ratio::new(BigInt::from_i64(numer), BigInt::from_i64(denom))
+    }
+
+    #[test]
+    fn ratios_are_kept_in_lowest_terms() {
+        assert_eq!(ratio(6, 4).to_string(), "3/2");
+        assert_eq!(ratio(3, -6).to_string(), "-1/2");
+        assert_eq!(ratio(-3, -6).to_string(), "1/2");
+        assert_eq!(ratio(0, -5).to_string(), "0/1");
+        assert_eq!(ratio(-4, 2), Ratio::from_integer(BigInt::from_i64(-2)));
+        assert!(ratio(-4, -2).is_integer());
+        assert_eq!(ratio(i64::MIN, -2).to_string(), "4611686018427387904/1");
+        assert_eq!(ratio(1, 3).sub(&ratio(1, 3)), ratio(0, 1));
+        assert_eq!(ratio(1, 6).add(&ratio(1, 3)), ratio(1, 2));
+        assert_eq!(ratio(1, 2).div(&ratio(-1, 4)), ratio(-2, 1));
+    }
+
+    #[test]
+    fn rounding_follows_the_sign() {
+        let cases = [
+            (ratio(7, 2), 3, 4, 3, 4),
+            (ratio(-7, 2), -4, -3, -3, -4),
+            (ratio(5, 2), 2, 3, 2, 2),
+            (ratio(-5, 2), -3, -2, -2, -2),
+            (ratio(-1, 3), -1, 0, 0, 0),
+        ];
+        for (n, floor, ceil, trunc, round) in cases {
+            let expected = [floor, ceil, trunc, round].map(BigInt::from_i64);
+            assert_eq!(
+                [n.floor(), n.ceil(), n.trunc(), n.round()],
+                expected,
+                "{}",
+                n
+            );
+        }
+    }
+
+    #[test]
+    fn floats_convert_exactly() {
+        assert_eq!(Ratio::from_f64(0.5), Some(ratio(1, 2)));
+        assert_eq!(Ratio::from_f64(-3.0), Some(ratio(-3, 1)));
+        assert_eq!(
+            Ratio::from_f64(0.1),
+            Some(ratio(3602879701896397, 36028797018963968))
+        );
+        assert_eq!(
+            Ratio::from_f64(f64::MIN_POSITIVE / 4.0).unwrap().denom(),
+            &BigInt::pow2(1024)
+        );
+        assert_eq!(Ratio::from_f64(f64::NAN), None);
+        assert_eq!(Ratio::from_f64(f64::INFINITY), None);
+    }
+
+    #[test]
+    fn simplest_between_finds_the_smallest_denominator() {
+        let cases = [
+            (ratio(3, 10), ratio(2, 5), ratio(1, 3)),
+            (ratio(2, 5), ratio(3, 10), ratio(1, 3)),
+            (ratio(-2, 5), ratio(-3, 10), ratio(-1, 3)),
+            (ratio(-1, 2), ratio(1, 2), ratio(0, 1)),
+            (ratio(3, 2), ratio(5, 2), ratio(2, 1)),
+            (ratio(5, 4), ratio(5, 4), ratio(5, 4)),
+            (ratio(7, 3), ratio(12, 5), ratio(7, 3)),
+            (
+                Ratio::from_f64(0.1).unwrap(),
+                Ratio::from_f64(0.1).unwrap(),
+                Ratio::from_f64(0.1).unwrap(),
+            ),
+        ];
+        for (lo, hi, expected) in cases {
+            assert_eq!(Ratio::simplest_between(&lo, &hi), expected, "{} {}", lo, hi);
+        }
+
+        let third = Ratio::from_f64(1.0 / 3.0).unwrap();
+        let epsilon = Ratio::from_f64(1e-10).unwrap();
+        assert_eq!(
+            Ratio::simplest_between(&third.sub(&epsilon), &third.add(&epsilon)),
+            ratio(1, 3)
+        );
+    }
+}
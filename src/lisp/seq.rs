@@ -0,0 +1,136 @@
+use crate::{
+    interpreter::{EvalError, Evaluator},
+    lisp::{internal::*, list::expect_list},
+};
+
+/// The argument lists for calling a function across `lists` element-wise,
+/// stopping at the end of the shortest list.
+fn zip_lists(lists: &[LispExpr]) -> Result<Vec<Vec<LispExpr>>, EvalError> {
+    let lists = lists
+        .iter()
+        .map(expect_list)
+        .collect::<Result<Vec<_>, _>>()?;
+    let len = lists.iter().map(Vec::len).min().unwrap_or(0);
+    Ok((0..len)
+        .map(|i| lists.iter().map(|list| list[i].clone()).collect())
+        .collect())
+}
+
+/// `(map f list...)` calls `f` with the elements of each list in turn, and
+/// collects the results into a list as long as the shortest list.
+pub fn lisp_map(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let results = zip_lists(&args[1..])?
+        .into_iter()
+        .map(|row| eval.apply(&args[0], row))
+        .collect::<Result<_, _>>()?;
+    Ok(LispExpr::list(results))
+}
+
+/// Like `map`, for side effects only.
+pub fn lisp_for_each(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    for row in zip_lists(&args[1..])? {
+        eval.apply(&args[0], row)?;
+    }
+    Ok(LispExpr::Null)
+}
+
+pub fn lisp_filter(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let mut kept = Vec::new();
+    for item in expect_list(&args[1])? {
+        if eval.apply(&args[0], vec![item.clone()])?.is_truthy() {
+            kept.push(item);
+        }
+    }
+    Ok(LispExpr::list(kept))
+}
+
+/// `(reduce f init list)` folds from the left, calling `(f acc item)` for
+/// each item with `acc` starting at `init`. Also available as `fold`.
+pub fn lisp_reduce(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let mut acc = args[1].clone();
+    for item in expect_list(&args[2])? {
+        acc = eval.apply(&args[0], vec![acc, item])?;
+    }
+    Ok(acc)
+}
+
+/// `(apply f arg... list)` calls `f` with the arguments followed by the
+/// elements of `list`.
+pub fn lisp_apply(eval: &Evaluator, mut args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let spread = expect_list(&args.pop().unwrap())?;
+    let func = args.remove(0);
+    args.extend(spread);
+    eval.apply(&func, args)
+}
+
+/// The first element satisfying the predicate, or nil.
+pub fn lisp_find(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    for item in expect_list(&args[1])? {
+        if eval.apply(&args[0], vec![item.clone()])?.is_truthy() {
+            return Ok(item);
+        }
+    }
+    Ok(LispExpr::Null)
+}
+
+/// Nil as soon as the predicate fails across the lists, and otherwise the
+/// result of its last call, or `t` if there were none.
+pub fn lisp_every(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let mut result = LispExpr::from_bool(true);
+    for row in zip_lists(&args[1..])? {
+        result = eval.apply(&args[0], row)?;
+        if !result.is_truthy() {
+            break;
+        }
+    }
+    Ok(result)
+}
+
+/// The first true result of the predicate across the lists, or nil.
+pub fn lisp_some(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    for row in zip_lists(&args[1..])? {
+        let result = eval.apply(&args[0], row)?;
+        if result.is_truthy() {
+            return Ok(result);
+        }
+    }
+    Ok(LispExpr::Null)
+}
+
+/// Stable merge sort calling `less` once per comparison. Unlike the slice
+/// sorts it tolerates an inconsistent predicate and stops on its errors.
+fn merge_sort(
+    eval: &Evaluator,
+    mut items: Vec<LispExpr>,
+    less: &LispExpr,
+) -> Result<Vec<LispExpr>, EvalError> {
+    if items.len() <= 1 {
+        return Ok(items);
+    }
+
+    let right = items.split_off(items.len() / 2);
+    let mut left = merge_sort(eval, items, less)?.into_iter().peekable();
+    let mut right = merge_sort(eval, right, less)?.into_iter().peekable();
+
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+        // Only take from the right when strictly less, to keep equal
+        // elements in their original order
+        let next = if eval.apply(less, vec![b.clone(), a.clone()])?.is_truthy() {
+            right.next()
+        } else {
+            left.next()
+        };
+        merged.extend(next);
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
+
+/// `(sort list less)` returns a new list ordered by the predicate `less`,
+/// keeping equal elements in their original order.
+pub fn lisp_sort(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let items = expect_list(&args[0])?;
+    Ok(LispExpr::list(merge_sort(eval, items, &args[1])?))
+}
@@ -0,0 +1,182 @@
+use crate::{
+    interpreter::{EvalError, Evaluator},
+    lisp::{
+        arith::expect_radix,
+        bigint::BigInt,
+        internal::*,
+        list::{expect_index, expect_list},
+        number::LispNumber,
+        ratio::Ratio,
+    },
+    token::{TokenValue, Tokenizer},
+};
+
+pub(crate) fn expect_string(expr: &LispExpr) -> Result<&str, EvalError> {
+    match expr {
+        LispExpr::String(s) => Ok(s),
+        expr => Err(expr.mismatch(LispTypeId::String)),
+    }
+}
+
+/// Checks for between `min` and `max` arguments.
+/// The byte offset of the character at `index`, which may be one past the
+/// last character.
+fn char_offset(s: &str, index: usize) -> Result<usize, EvalError> {
+    s.char_indices()
+        .map(|(offset, _)| offset)
+        .chain([s.len()])
+        .nth(index)
+        .ok_or(EvalError::OutOfRange)
+}
+
+fn string(s: impl Into<Box<str>>) -> LispExpr {
+    LispExpr::String(s.into().into())
+}
+
+pub fn lisp_string_append(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let mut result = String::new();
+    for arg in &args {
+        result.push_str(expect_string(arg)?);
+    }
+    Ok(string(result))
+}
+
+/// The number of characters, rather than bytes, in a string.
+pub fn lisp_string_length(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let length = expect_string(&args[0])?.chars().count();
+    Ok(LispExpr::Number(LispNumber::Integer(length as i64)))
+}
+
+/// `(substring str start end)` is the characters from index `start` up to
+/// but excluding `end`, which defaults to the end of the string.
+pub fn lisp_substring(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let s = expect_string(&args[0])?;
+    let start = char_offset(s, expect_index(&args[1])?)?;
+    let end = match args.get(2) {
+        Some(end) => char_offset(s, expect_index(end)?)?,
+        None => s.len(),
+    };
+    if start > end {
+        return Err(EvalError::OutOfRange);
+    }
+    Ok(string(&s[start..end]))
+}
+
+/// `(string-split str sep)` splits on every occurrence of `sep`, or on runs
+/// of whitespace without one. An empty `sep` splits into characters.
+pub fn lisp_string_split(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let s = expect_string(&args[0])?;
+    let parts: Vec<LispExpr> = match args.get(1).map(expect_string).transpose()? {
+        None => s.split_whitespace().map(string).collect(),
+        Some("") => s.chars().map(|c| string(c.to_string())).collect(),
+        Some(sep) => s.split(sep).map(string).collect(),
+    };
+    Ok(LispExpr::list(parts))
+}
+
+/// `(string-join list sep)` concatenates a list of strings with `sep`, or
+/// nothing, between them.
+pub fn lisp_string_join(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let sep = match args.get(1) {
+        Some(sep) => expect_string(sep)?,
+        None => "",
+    };
+    let parts = expect_list(&args[0])?;
+    let parts = parts
+        .iter()
+        .map(expect_string)
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(string(parts.join(sep)))
+}
+
+/// Removes leading and trailing whitespace.
+pub fn lisp_string_trim(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    Ok(string(expect_string(&args[0])?.trim()))
+}
+
+pub fn lisp_string_upcase(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    Ok(string(expect_string(&args[0])?.to_uppercase()))
+}
+
+pub fn lisp_string_downcase(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    Ok(string(expect_string(&args[0])?.to_lowercase()))
+}
+
+/// `(string-index str pattern)` is the character index of the first
+/// occurrence of `pattern` in `str`, or nil.
+pub fn lisp_string_index(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let s = expect_string(&args[0])?;
+    let pattern = expect_string(&args[1])?;
+    Ok(match s.find(pattern) {
+        Some(offset) => {
+            let index = s[..offset].chars().count();
+            LispExpr::Number(LispNumber::Integer(index as i64))
+        }
+        None => LispExpr::Null,
+    })
+}
+
+pub fn lisp_string_contains(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let s = expect_string(&args[0])?;
+    let pattern = expect_string(&args[1])?;
+    Ok(LispExpr::from_bool(s.contains(pattern)))
+}
+
+/// `(string-replace str from to)` replaces every occurrence of `from`.
+pub fn lisp_string_replace(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let s = expect_string(&args[0])?;
+    let from = expect_string(&args[1])?;
+    let to = expect_string(&args[2])?;
+    if from.is_empty() {
+        return Ok(args[0].clone());
+    }
+    Ok(string(s.replace(from, to)))
+}
+
+/// The characters of a string, as a list of one-character strings.
+pub fn lisp_string_to_list(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let s = expect_string(&args[0])?;
+    Ok(LispExpr::list(
+        s.chars().map(|c| string(c.to_string())).collect(),
+    ))
+}
+
+/// Parses an integer or ratio written in `radix`, with an optional sign.
+fn parse_exact(text: &str, radix: u32) -> Option<LispNumber> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let (numer, denom) = match digits.split_once('/') {
+        Some((numer, denom)) => (numer, BigInt::parse_radix(denom, radix)?),
+        None => (digits, BigInt::from_i64(1)),
+    };
+    let numer = BigInt::parse_radix(numer, radix)?;
+    if denom.is_zero() {
+        return None;
+    }
+    let numer = if negative { numer.neg() } else { numer };
+    Some(LispNumber::from_ratio(Ratio::new(numer, denom)))
+}
+
+/// `(string->number str radix)` reads a number the way the reader would,
+/// or as an integer or ratio in another `radix`. Gives nil if `str` isn't
+/// exactly one number.
+pub fn lisp_string_to_number(eval: &Evaluator, args: Vec<LispExpr>) -> Result<LispExpr, EvalError> {
+    let s = expect_string(&args[0])?;
+    let radix = expect_radix(args.get(1))?;
+
+    let n = if radix == 10 {
+        let mut tokens = Tokenizer::new(s);
+        match (tokens.next(), tokens.next()) {
+            (Some(Ok(token)), None) => match token.value {
+                TokenValue::Number(n) => Some(n),
+                _ => None,
+            },
+            _ => None,
+        }
+    } else {
+        parse_exact(s, radix)
+    };
+    Ok(n.map_or(LispExpr::Null, LispExpr::Number))
+}
@@ -0,0 +1,104 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// An interned symbol name. Symbols are small ids into a per-thread table,
+/// so comparing or hashing them never touches the name itself.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+struct Interner {
+    names: Vec<Rc<str>>,
+    ids: HashMap<Rc<str>, Symbol>,
+    /// Only interned symbols are looked up by name; ids made by `gensym`
+    /// have a name for printing but are never returned by `intern`.
+    interned: Vec<bool>,
+}
+
+macro_rules! well_known_symbols {
+    ($($ident:ident = $name:expr),* $(,)?) => {
+        const WELL_KNOWN: &[&str] = &[$($name),*];
+        well_known_symbols!(@ids 0, $($ident),*);
+    };
+    (@ids $n:expr, $ident:ident $(, $rest:ident)*) => {
+        pub const $ident: Symbol = Symbol($n);
+        well_known_symbols!(@ids $n + 1, $($rest),*);
+    };
+    (@ids $n:expr,) => {};
+}
+
+// Symbols the evaluator matches on, given fixed ids so they can be compared
+// without a table lookup
+well_known_symbols! {
+    QUOTE = "quote",
+    DEFINE = "define",
+    LAMBDA = "lambda",
+    IF = "if",
+    COND = "cond",
+    ELSE = "else",
+    BEGIN = "begin",
+    NIL = "nil",
+    T = "t",
+    OPTIONAL = "&optional",
+    REST = "&rest",
+    KEY = "&key",
+}
+
+thread_local! {
+    static INTERNER: RefCell<Interner> = RefCell::new(Interner::new());
+}
+
+impl Interner {
+    fn new() -> Interner {
+        let mut interner = Interner {
+            names: Vec::new(),
+            ids: HashMap::new(),
+            interned: Vec::new(),
+        };
+        for name in WELL_KNOWN {
+            interner.intern(name);
+        }
+        interner
+    }
+
+    fn push(&mut self, name: Rc<str>, interned: bool) -> Symbol {
+        let sym = Symbol(self.names.len() as u32);
+        self.names.push(name);
+        self.interned.push(interned);
+        sym
+    }
+
+    fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(sym) = self.ids.get(name) {
+            return *sym;
+        }
+        let name: Rc<str> = Rc::from(name);
+        let sym = self.push(name.clone(), true);
+        self.ids.insert(name, sym);
+        sym
+    }
+}
+
+impl Symbol {
+    pub fn intern(name: &str) -> Symbol {
+        INTERNER.with(|interner| interner.borrow_mut().intern(name))
+    }
+
+    /// Creates a fresh symbol that is distinct from every other symbol,
+    /// including any later symbol read with the same name.
+    pub fn gensym() -> Symbol {
+        INTERNER.with(|interner| {
+            let mut interner = interner.borrow_mut();
+            let name = format!("G{}", interner.names.len());
+            interner.push(name.into(), false)
+        })
+    }
+
+    pub fn name(self) -> Rc<str> {
+        INTERNER.with(|interner| interner.borrow().names[self.0 as usize].clone())
+    }
+
+    pub fn is_interned(self) -> bool {
+        INTERNER.with(|interner| interner.borrow().interned[self.0 as usize])
+    }
+}
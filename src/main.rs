@@ -2,39 +2,127 @@
 // TODO: Enable later
 #![allow(dead_code)]
 #![allow(unused_variables)]
-use std::{env, fs};
+use std::{env, fs, process, thread};
 
-use crate::{ast::ASTParser, interpreter::Evaluator, token::Tokenizer};
+use crate::{
+    ast::ASTParser,
+    bytecode::{
+        chunk::disassemble,
+        compiler::Compiler,
+        module::{is_module, read_module, write_module},
+    },
+    interpreter::{Backend, Evaluator, STACK_SIZE},
+    token::Tokenizer,
+};
 
 mod ast;
+mod bytecode;
 mod interpreter;
 mod lisp;
 mod token;
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
+    // Recursion in the evaluator nests on the native stack, so it gets a
+    // thread with the stack its default max depth was sized for
+    let runner = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(run)
+        .unwrap();
+    if runner.join().is_err() {
+        process::exit(101);
+    }
+}
+
+fn run() {
+    // Usage: lisp [--vm] [--disassemble] [--compile OUT] FILE
+    let mut backend = Backend::TreeWalk;
+    let mut disassembly = false;
+    let mut output = None;
+    let mut path = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--vm" => backend = Backend::Bytecode,
+            "--disassemble" => disassembly = true,
+            "--compile" => output = args.next(),
+            _ => path = Some(arg),
+        }
+    }
+    let Some(path) = path else {
+        return;
+    };
+
+    let bytes = fs::read(path).unwrap();
+
+    // Precompiled modules skip straight to the VM
+    if is_module(&bytes) {
+        match read_module(&bytes) {
+            Ok(forms) if disassembly => {
+                for proto in &forms {
+                    println!("{}", disassemble(proto));
+                }
+            }
+            Ok(forms) => {
+                let mut eval = Evaluator::new();
+                if let Err(e) = eval.run_compiled(forms) {
+                    println!("{:?}", e);
+                }
+            }
+            Err(e) => println!("{:?}", e),
+        }
         return;
     }
 
-    let example = fs::read_to_string(&args[1]).unwrap();
-
-    let mut tknz = Tokenizer::new(example);
-    if let Err(e) = tknz.try_parse_all() {
-        println!("{:?}", (tknz.location, e));
-    } else {
-        let mut parser = ASTParser::new(tknz.tokens);
-        match parser.try_parse_all() {
-            Ok(()) => {
-                let mut eval = Evaluator::new(parser.roots);
-                match eval.run() {
-                    Ok(()) => {}
-                    Err(e) => println!("{:?}", e),
+    let example = String::from_utf8(bytes).unwrap();
+
+    let forms = ASTParser::new(Tokenizer::new(&example));
+
+    if output.is_some() || disassembly {
+        let mut protos = Vec::new();
+        for node in forms {
+            let compiled = match node {
+                Ok(node) => Compiler::compile(&node),
+                Err(e) => {
+                    println!("{:?}", e);
+                    return;
+                }
+            };
+            match compiled {
+                Ok(proto) => protos.push(proto),
+                Err(e) => {
+                    println!("{:?}", e);
+                    return;
+                }
+            }
+        }
+
+        match output {
+            Some(output) => match write_module(&protos) {
+                Ok(module) => fs::write(output, module).unwrap(),
+                Err(e) => println!("{:?}", e),
+            },
+            None => {
+                for proto in &protos {
+                    println!("{}", disassemble(proto));
                 }
             }
-            Err(e) => {
+        }
+        return;
+    }
+
+    // Each form runs as soon as it is parsed, so a syntax error stops the
+    // program where it occurs
+    let mut parse_error = None;
+    let nodes = forms.map_while(|node| node.map_err(|e| parse_error = Some(e)).ok());
+
+    let mut eval = Evaluator::new();
+    eval.set_backend(backend);
+    match eval.run(nodes) {
+        Ok(()) => {
+            if let Some(e) = parse_error {
                 println!("{:?}", e);
             }
         }
+        Err(e) => println!("{:?}", e),
     }
 }
@@ -1,19 +1,25 @@
 use std::fmt::Debug;
 
+use crate::lisp::{bigint::BigInt, number::LispNumber, ratio::Ratio};
+
 #[derive(Clone, Debug)]
 pub enum TokenValue {
     LParen,
     RParen,
     Symbol(String),
-    Number(f32),
+    Number(LispNumber),
     String(String),
+    Keyword(String),
     Quote,
+    Dot,
 }
 
 #[derive(Clone, Copy, Debug)]
 pub struct Location {
     pub row: usize,
     pub col: usize,
+    /// Byte offset into the source text
+    pub offset: usize,
 }
 
 #[derive(Clone)]
@@ -32,158 +38,290 @@ impl Debug for Token {
     }
 }
 
-pub struct Tokenizer {
-    input: String,
+pub struct Tokenizer<'a> {
+    input: &'a str,
+    offset: usize,
     pub location: Location,
-    pub tokens: Vec<Token>,
 }
 
 #[derive(Debug)]
 pub enum LexerError {
     TryFailed,
     MismatchedQuote,
+    EmptyKeyword,
+    MismatchedBar,
+    /// A `#x` prefix without any hex digits after it, or a ratio with a
+    /// zero denominator
+    InvalidNumber,
 }
 
 fn is_symbol(c: char) -> bool {
-    c.is_alphanumeric() || "+-*/%!^&|~<=>".contains(c)
+    c.is_alphanumeric() || "+-*/%!^&~<=>?_.$:".contains(c)
 }
 
-impl Tokenizer {
-    pub fn new(input: String) -> Tokenizer {
+/// Returns `name` as it must be written for the reader to produce the same
+/// symbol, wrapping it in `|...|` when it contains characters the plain
+/// symbol syntax doesn't allow or would be read as something else.
+pub fn escape_symbol(name: &str) -> String {
+    let plain = name != "."
+        && match name.chars().next() {
+            None | Some('0'..='9') | Some(':') => false,
+            // A sign followed by a digit reads as a number
+            Some('-' | '+') if name[1..].starts_with(|c: char| c.is_ascii_digit()) => false,
+            Some(_) => name.chars().all(is_symbol),
+        };
+
+    if plain {
+        return name.to_string();
+    }
+
+    let mut escaped = "|".to_string();
+    for c in name.chars() {
+        if c == '|' || c == '\\' {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped.push('|');
+    escaped
+}
+
+/// Parses decimal digits with an optional sign.
+fn parse_signed(text: &str) -> BigInt {
+    let n = BigInt::parse_radix(text.trim_start_matches(['-', '+']), 10).unwrap();
+    if text.starts_with('-') { n.neg() } else { n }
+}
+
+impl<'a> Tokenizer<'a> {
+    pub fn new(input: &'a str) -> Tokenizer<'a> {
         Tokenizer {
             input,
-            location: Location { row: 0, col: 0 },
-            tokens: Vec::new(),
+            offset: 0,
+            location: Location {
+                row: 0,
+                col: 0,
+                offset: 0,
+            },
         }
     }
 
     fn peek(&self) -> Option<char> {
-        self.input.chars().next()
-    }
-
-    fn next(&mut self) -> Option<char> {
-        let mut chars = self.input.chars();
-        match chars.next() {
-            Some(c) => {
-                if c == '\n' {
-                    self.location.row += 1;
-                    self.location.col = 0;
-                } else {
-                    self.location.col += 1;
-                }
-                self.input = chars.collect();
-                Some(c)
-            }
-            None => None,
+        self.input[self.offset..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.offset += c.len_utf8();
+        self.location.offset = self.offset;
+        if c == '\n' {
+            self.location.row += 1;
+            self.location.col = 0;
+        } else {
+            self.location.col += 1;
         }
+        Some(c)
     }
 
     fn trim(&mut self) {
         while let Some(c) = self.peek()
             && c.is_whitespace()
         {
-            self.next();
+            self.bump();
+        }
+    }
+
+    /// Reads the rest of a symbol name, where any part may be wrapped in
+    /// `|...|` to include arbitrary characters.
+    fn symbol_name(&mut self, mut name: String) -> Result<String, LexerError> {
+        loop {
+            name.push_str(self.take_while(is_symbol));
+            if self.peek() != Some('|') {
+                return Ok(name);
+            }
+            self.bump();
+            self.escaped_segment(&mut name)?;
         }
     }
 
-    fn push_token(&mut self, tok: TokenValue) {
-        self.tokens.push(Token {
-            location: self.location,
-            value: tok,
-        });
+    /// Reads up to the closing `|` of an escaped segment, with `\\` escaping
+    /// `|` and `\\`.
+    fn escaped_segment(&mut self, name: &mut String) -> Result<(), LexerError> {
+        loop {
+            match self.bump() {
+                Some('|') => return Ok(()),
+                Some('\\') => match self.bump() {
+                    Some(c) => name.push(c),
+                    None => return Err(LexerError::MismatchedBar),
+                },
+                Some(c) => name.push(c),
+                None => return Err(LexerError::MismatchedBar),
+            }
+        }
     }
 
-    pub fn try_parse_one(&mut self) -> Result<(), LexerError> {
-        self.trim();
+    /// Consumes characters while `pred` holds and returns them as a slice of
+    /// the input, without copying.
+    fn take_while(&mut self, pred: impl Fn(char) -> bool) -> &'a str {
+        let start = self.offset;
+        while let Some(c) = self.peek()
+            && pred(c)
+        {
+            self.bump();
+        }
+        &self.input[start..self.offset]
+    }
 
-        if let Some(c) = self.peek() {
-            match c {
-                '(' => {
-                    self.push_token(TokenValue::LParen);
-                    self.next();
-                }
-                ')' => {
-                    self.push_token(TokenValue::RParen);
-                    self.next();
+    /// Reads the rest of a decimal number or ratio starting at byte `start`,
+    /// which is either its first digit or a sign.
+    fn number(&mut self, start: usize) -> Result<LispNumber, LexerError> {
+        self.take_while(|c| c.is_ascii_digit());
+
+        if let Some('.') = self.peek() {
+            self.bump();
+            self.take_while(|c| c.is_ascii_digit());
+            let n = self.input[start..self.offset].parse().unwrap();
+            return Ok(LispNumber::Float(n));
+        }
+
+        let text = &self.input[start..self.offset];
+        if self.peek() == Some('/')
+            && self.input[self.offset + 1..].starts_with(|c: char| c.is_ascii_digit())
+        {
+            self.bump();
+            let denom = self.take_while(|c| c.is_ascii_digit());
+            let denom = BigInt::parse_radix(denom, 10).unwrap();
+            if denom.is_zero() {
+                return Err(LexerError::InvalidNumber);
+            }
+            return Ok(LispNumber::from_ratio(Ratio::new(
+                parse_signed(text),
+                denom,
+            )));
+        }
+
+        match text.parse() {
+            Ok(n) => Ok(LispNumber::Integer(n)),
+            Err(_) => Ok(LispNumber::from_big(parse_signed(text))),
+        }
+    }
+
+    fn lex_one(&mut self) -> Result<TokenValue, LexerError> {
+        let Some(c) = self.bump() else {
+            return Err(LexerError::TryFailed);
+        };
+
+        match c {
+            '(' => Ok(TokenValue::LParen),
+            ')' => Ok(TokenValue::RParen),
+            '\'' => Ok(TokenValue::Quote),
+            '"' => {
+                let mut s = String::new();
+                loop {
+                    match self.bump() {
+                        Some('"') => break,
+                        Some(c) => s.push(c),
+                        None => return Err(LexerError::MismatchedQuote),
+                    }
                 }
-                '\'' => {
-                    self.push_token(TokenValue::Quote);
-                    self.next();
+                Ok(TokenValue::String(s))
+            }
+            ':' => {
+                let name = self.symbol_name(String::new())?;
+                if name.is_empty() {
+                    return Err(LexerError::EmptyKeyword);
                 }
-                '"' => {
-                    self.next();
-                    let mut s = String::new();
-                    loop {
-                        if let Some(c) = self.next() {
-                            if c == '"' {
-                                break;
-                            } else {
-                                s.push(c);
-                            }
-                        } else {
-                            return Err(LexerError::MismatchedQuote);
-                        }
+                Ok(TokenValue::Keyword(name))
+            }
+            '0'..='9' => self.number(self.offset - 1).map(TokenValue::Number),
+            '-' | '+' if self.peek().is_some_and(|c| c.is_ascii_digit()) => {
+                self.number(self.offset - 1).map(TokenValue::Number)
+            }
+            '#' => match self.bump() {
+                Some('x' | 'X') => {
+                    let digits = self.take_while(|c| c.is_ascii_hexdigit());
+                    match BigInt::parse_radix(digits, 16) {
+                        Some(n) => Ok(TokenValue::Number(LispNumber::from_big(n))),
+                        None => Err(LexerError::InvalidNumber),
                     }
-                    self.push_token(TokenValue::String(s));
                 }
-                '0'..'9' => {
-                    let mut digits: f32 = 0.0;
-                    let mut floating: f32 = 1.0;
-
-                    while let Some(c) = self.peek()
-                        && c.is_digit(10)
-                    {
-                        self.next();
-                        digits = 10.0 * digits + c.to_digit(10).unwrap() as f32;
-                    }
+                _ => Err(LexerError::TryFailed),
+            },
+            '|' => {
+                let mut name = String::new();
+                self.escaped_segment(&mut name)?;
+                Ok(TokenValue::Symbol(self.symbol_name(name)?))
+            }
+            '.' if !self.peek().is_some_and(|c| is_symbol(c) || c == '|') => Ok(TokenValue::Dot),
+            c if is_symbol(c) => Ok(TokenValue::Symbol(self.symbol_name(c.to_string())?)),
+            _ => Err(LexerError::TryFailed),
+        }
+    }
+}
 
-                    if let Some('.') = self.peek() {
-                        self.next();
-                        while let Some(c) = self.peek()
-                            && c.is_digit(10)
-                        {
-                            self.next();
-                            digits = 10.0 * digits + c.to_digit(10).unwrap() as f32;
-                            floating *= 10.0;
-                        }
-                    }
+impl Iterator for Tokenizer<'_> {
+    /// A token, or an error with the location of the token it occurred in
+    type Item = Result<Token, (Location, LexerError)>;
 
-                    self.push_token(TokenValue::Number(digits / floating));
-                }
-                c if is_symbol(c) => {
-                    let mut sym = String::new();
-                    while let Some(c) = self.peek()
-                        && is_symbol(c)
-                    {
-                        self.next();
-                        sym.push(c);
-                    }
-                    self.push_token(TokenValue::Symbol(sym));
-                }
-                _ => return Err(LexerError::TryFailed),
+    fn next(&mut self) -> Option<Self::Item> {
+        self.trim();
+        self.peek()?;
+
+        let location = self.location;
+        match self.lex_one() {
+            Ok(value) => Some(Ok(Token { value, location })),
+            Err(e) => {
+                // Stop at the first error
+                self.offset = self.input.len();
+                Some(Err((location, e)))
             }
         }
+    }
+}
 
-        Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::ASTParser;
+    use crate::interpreter::Evaluator;
+    use crate::lisp::{function::expr_to_readable, internal::LispExpr, symbol::Symbol};
+
+    fn read(source: &str) -> LispExpr {
+        let node = ASTParser::new(Tokenizer::new(source)).next().unwrap();
+        Evaluator::node_to_expr(node.unwrap())
     }
 
-    pub fn try_parse_all(&mut self) -> Result<(), LexerError> {
-        loop {
-            if let None = self.peek() {
-                return Ok(());
-            }
+    /// Checks that `source` prints back exactly as written, and that the
+    /// printed text reads back as the same value.
+    fn assert_round_trips(source: &str) {
+        let value = read(source);
+        let printed = expr_to_readable(&value).unwrap();
+        assert_eq!(printed, source);
+        assert!(
+            read(&printed).equal(&value),
+            "{} changed on re-reading",
+            source
+        );
+    }
 
-            match self.try_parse_one() {
-                Ok(()) => {}
-                Err(LexerError::TryFailed) => {
-                    if self.peek().is_none() {
-                        return Ok(());
-                    } else {
-                        return Err(LexerError::TryFailed);
-                    }
-                }
-                Err(e) => return Err(e),
-            }
+    #[test]
+    fn escaped_symbols_round_trip() {
+        for source in ["|a b|", "|1abc|", "||", "|.|", "|-1|", "|a\\|b|", ":|x y|"] {
+            assert_round_trips(source);
+        }
+    }
+
+    #[test]
+    fn escaped_symbols_read_as_their_contents() {
+        assert!(read("|a b|").equal(&LispExpr::Symbol(Symbol::intern("a b"))));
+        assert!(read("|foo|").equal(&read("foo")));
+        assert!(read("|a\\|b|").equal(&LispExpr::Symbol(Symbol::intern("a|b"))));
+    }
+
+    #[test]
+    fn extended_symbols_print_unescaped() {
+        for source in ["null?", "my_var", "a.b", "$tmp", "a:b", "-", "+", "-a"] {
+            assert_eq!(escape_symbol(source), source);
+            assert_round_trips(source);
         }
     }
 }